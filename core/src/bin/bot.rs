@@ -0,0 +1,191 @@
+//Headless peer joining a local UDP or matchbox session, so the mod can be tested without a second game copy
+//Its dummy state never matches the game one, desync events are expected and only logged
+//Usage : bot <config.json> [--inputs neutral|random:<seed>|replay:<path>] [--state-size <bytes>]
+use std::env;
+use std::fs;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use ggrs_ffi::{
+    config::app_config::{AppConfig, InputGeneratorConfig},
+    core::{
+        checksum::ChecksumSource, input_generator::InputGenerator, unmanaged::safe_bytes::SafeBytes,
+    },
+    model::{game_state::GameState, input::Input, netplay_request::NetplayRequest},
+    neplay::Netplay,
+};
+use tracing::{error, info, trace, warn};
+
+const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / 60);
+const DEFAULT_STATE_SIZE: usize = 64;
+
+struct BotArgs {
+    config: AppConfig,
+    inputs: InputGeneratorConfig,
+    state_size: usize,
+}
+
+fn parse_args() -> Result<BotArgs, String> {
+    let mut args = env::args().skip(1);
+
+    let config_path = args
+        .next()
+        .ok_or("Usage : bot <config.json> [--inputs neutral|random:<seed>|replay:<path>] [--state-size <bytes>]")?;
+    let config_bytes =
+        fs::read(&config_path).map_err(|e| format!("Couldn't read {} : {}", config_path, e))?;
+    let config: AppConfig = serde_json::from_slice(&config_bytes)
+        .map_err(|e| format!("Invalid config {} : {}", config_path, e))?;
+
+    let mut inputs = InputGeneratorConfig::Neutral;
+    let mut state_size = DEFAULT_STATE_SIZE;
+
+    while let Some(arg) = args.next() {
+        let value = args.next().ok_or(format!("Missing value for {}", arg))?;
+
+        match arg.as_str() {
+            "--inputs" => {
+                inputs = match value.split_once(':') {
+                    Some(("random", seed)) => InputGeneratorConfig::Random(
+                        seed.parse().map_err(|e| format!("Invalid seed : {}", e))?,
+                    ),
+                    Some(("replay", path)) => InputGeneratorConfig::Replay(path.to_string()),
+                    None if value == "neutral" => InputGeneratorConfig::Neutral,
+                    _ => return Err(format!("Unknown input mode {}", value)),
+                }
+            }
+            "--state-size" => {
+                state_size = value
+                    .parse()
+                    .map_err(|e| format!("Invalid state size : {}", e))?
+            }
+            _ => return Err(format!("Unknown argument {}", arg)),
+        }
+    }
+
+    Ok(BotArgs {
+        config,
+        inputs,
+        state_size,
+    })
+}
+
+//Stands in for the game : the frame number followed by a running hash of the inputs, padded to the wanted size
+struct DummyState {
+    bytes: Vec<u8>,
+}
+
+impl DummyState {
+    fn new(size: usize) -> Self {
+        Self {
+            bytes: vec![0; size.max(12)],
+        }
+    }
+
+    fn advance(&mut self, inputs: &[Input]) {
+        let frame = i32::from_le_bytes(self.bytes[0..4].try_into().unwrap()) + 1;
+        let mut hash = u64::from_le_bytes(self.bytes[4..12].try_into().unwrap());
+
+        for input in inputs {
+            for byte in bytemuck::bytes_of(input) {
+                hash = hash.wrapping_mul(1_099_511_628_211) ^ *byte as u64;
+            }
+        }
+
+        self.bytes[0..4].copy_from_slice(&frame.to_le_bytes());
+        self.bytes[4..12].copy_from_slice(&hash.to_le_bytes());
+    }
+}
+
+unsafe fn handle_requests(netplay: &mut Netplay, state: &mut DummyState) -> Result<(), String> {
+    for request in netplay.requests() {
+        match request {
+            NetplayRequest::SaveGameState => {
+                let size = state.bytes.len();
+                let game_state =
+                    GameState::new(SafeBytes::new(state.bytes.as_mut_ptr(), state.bytes.len()));
+
+                netplay.handle_save_game_state_request(game_state, ChecksumSource::Full)?;
+                trace!("Saved {} bytes", size);
+            }
+            NetplayRequest::LoadGameState => {
+                let loaded = netplay.handle_load_game_state_request()?;
+
+                state.bytes = loaded.slice().to_vec();
+                loaded.free();
+                trace!("Loaded {} bytes", state.bytes.len());
+            }
+            NetplayRequest::AdvanceFrame => {
+                let inputs = netplay.handle_advance_frame_request();
+                state.advance(&inputs);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn main() {
+    tracing_subscriber::fmt()
+        .compact()
+        .with_target(false)
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut generator = match InputGenerator::new(&args.inputs, 0) {
+        Ok(generator) => generator,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut netplay = Netplay::new(None);
+
+    if let Err(e) = unsafe { netplay.init(args.config) } {
+        error!("Couldn't start session : {}", e);
+        std::process::exit(1);
+    }
+
+    let mut state = DummyState::new(args.state_size);
+    let mut next_frame = Instant::now();
+
+    loop {
+        if let Err(e) = netplay.poll_remote() {
+            warn!("{}", e);
+        }
+
+        for event in netplay.events() {
+            info!("{}", event);
+
+            if event.starts_with("Disconnected") {
+                return;
+            }
+        }
+
+        if netplay.is_synchronized() {
+            let frame = netplay.game_state().frame();
+
+            match netplay.advance_frame(generator.next(frame, Input::default())) {
+                Ok(()) => {
+                    if let Err(e) = unsafe { handle_requests(&mut netplay, &mut state) } {
+                        error!("{}", e);
+                        std::process::exit(1);
+                    }
+                }
+                Err(e) if e == "PredictionThreshold" => {}
+                Err(e) => warn!("{}", e),
+            }
+        }
+
+        next_frame += FRAME_DURATION;
+        sleep(next_frame.saturating_duration_since(Instant::now()));
+    }
+}
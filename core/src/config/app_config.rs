@@ -9,6 +9,16 @@ pub struct AppConfig {
     name: String, //TODO: remove this, useless here
     pub netplay: NetplayConfig,
     pub test: Option<TestConfig>,
+    #[serde(default)]
+    pub disconnected_input_policy: DisconnectedInputPolicy,
+    #[serde(default)]
+    pub state_storage: StateStorageConfig,
+    #[serde(default)]
+    pub checksum: ChecksumAlgorithm,
+    pub desync_dump: Option<DesyncDumpConfig>,
+    pub network_simulation: Option<NetworkSimulationConfig>,
+    #[serde(default = "default_max_packet_size")]
+    pub max_packet_size: usize, //Bigger incoming packets are dropped
 }
 
 impl AppConfig {
@@ -30,14 +40,84 @@ pub struct NetplayConfig {
     pub local_conf: Option<NetplayLocalConfig>,
     pub server_conf: Option<NetplayServerConfig>,
     pub spectator_conf: Option<NetplaySpectatorConfig>,
+    #[serde(default)]
+    pub metadata: PlayerMetadata, //Sent to the other peers while connecting
+}
+
+impl NetplayConfig {
+    //Metadata sent to the other peers, with the display name of older server configs when it has none
+    pub fn local_metadata(&self) -> PlayerMetadata {
+        let mut metadata = self.metadata.clone();
+
+        if metadata.display_name.is_empty() {
+            if let Some(display_name) = self
+                .server_conf
+                .as_ref()
+                .and_then(|server| server.display_name.clone())
+            {
+                metadata.display_name = display_name;
+            }
+        }
+
+        metadata
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialOrd, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PlayerMetadata {
+    #[serde(default)]
+    pub display_name: String,
+    #[serde(default)]
+    pub archer_color: i32,
+    #[serde(default)]
+    pub mod_version: String,
 }
 
 #[derive(Clone, Debug, PartialOrd, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct NetplayLocalConfig {
-    pub remote_addr: String,
-    pub port: u16,
+    #[serde(default)]
+    pub remote_addr: String, //Ip or hostname with port, for two players sessions
+    pub port: u16,                 //0 lets the system pick one
+    pub bind_addr: Option<String>, //Dual stack when not set
+    #[serde(default)]
     pub player_draw: u32,
+    #[serde(default)]
+    pub players: Vec<NetplayLocalPlayer>, //Replaces remote addr and player draw when set
+    #[serde(default)]
+    pub spectators: Vec<String>, //Direct UDP only, matchbox spectators need a ServerConf session
+    pub pre_shared_key: Option<String>, //Both peers need the same one, packets from anyone else are dropped
+    #[serde(default)]
+    pub encrypt: bool, //Only with a pre shared key, packets are authenticated otherwise
+    pub rendezvous: Option<NetplayRendezvousConfig>, //Replaces remote addr and player draw for two players sessions
+    pub announce: Option<NetplayAnnounceConfig>, //Waits for a LAN peer instead of using remote addr
+    #[serde(default)]
+    pub join_announced: bool, //Remote addr is an announcing host to join, set by netplay_discovery_join
+}
+
+#[derive(Clone, Debug, PartialOrd, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct NetplayAnnounceConfig {
+    pub name: String,
+    #[serde(default)]
+    pub game_version: String,
+    #[serde(default = "default_announce_wait_secs")]
+    pub wait_secs: u64,
+}
+
+#[derive(Clone, Debug, PartialOrd, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct NetplayRendezvousConfig {
+    pub server: String,  //Ip or hostname with port of the rendezvous server
+    pub session: String, //Both peers register with the same one
+}
+
+#[derive(Clone, Debug, PartialOrd, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct NetplayLocalPlayer {
+    pub handle: usize,
+    pub addr: Option<String>, //None for the local player
 }
 
 #[derive(Clone, Debug, PartialOrd, PartialEq, Serialize, Deserialize)]
@@ -45,6 +125,8 @@ pub struct NetplayLocalConfig {
 pub struct NetplayServerConfig {
     pub room_url: Option<String>,
     pub is_host: bool,
+    pub udp_port: Option<u16>, //Needed by the host when some spectators watch over direct UDP
+    pub display_name: Option<String>, //Older configs, Metadata.DisplayName replaces it
 }
 
 //Add a spectator config
@@ -53,10 +135,139 @@ pub struct NetplayServerConfig {
 pub struct NetplaySpectatorConfig {
     pub room_url: Option<String>,
     pub to_spectate: Option<String>,
+    pub host_addr: Option<String>, //Spectate the host over direct UDP instead of matchbox
+    pub port: Option<u16>,
 }
 
 #[derive(Clone, Debug, PartialOrd, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct TestConfig {
     pub check_distance: i32,
+    #[serde(default)]
+    pub capture_states: bool, //Keep both buffers of a mismatch for the synctest report
+    #[serde(default)]
+    pub input_generator: InputGeneratorConfig,
+    #[serde(default = "default_test_players")]
+    pub num_players: usize,
+    #[serde(default = "default_test_host_handles")]
+    pub host_handles: Vec<usize>, //Handles fed with the host input (one each with netplay_advance_frame_with_inputs), the others use the input generator
+}
+
+impl TestConfig {
+    pub fn generated_handles(&self) -> Vec<usize> {
+        (0..self.num_players)
+            .filter(|handle| !self.host_handles.contains(handle))
+            .collect()
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.num_players < 2 {
+            return Err(format!(
+                "Test session needs at least 2 players, got {}",
+                self.num_players
+            ));
+        }
+
+        if self.host_handles.is_empty() {
+            return Err("Test session needs at least one host handle".to_string());
+        }
+
+        for (index, handle) in self.host_handles.iter().enumerate() {
+            if *handle >= self.num_players {
+                return Err(format!(
+                    "Test host handle {} out of range for {} players",
+                    handle, self.num_players
+                ));
+            }
+
+            if self.host_handles[..index].contains(handle) {
+                return Err(format!("Test host handle {} listed twice", handle));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+//Degrades outgoing packets, applied on every peer that has it in its config
+#[derive(Clone, Debug, PartialOrd, PartialEq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "PascalCase")]
+pub struct NetworkSimulationConfig {
+    #[serde(default)]
+    pub latency_ms: u64,
+    #[serde(default)]
+    pub jitter_ms: u64,
+    #[serde(default)]
+    pub loss: f64, //Probabilities between 0 and 1
+    #[serde(default)]
+    pub duplication: f64,
+    #[serde(default)]
+    pub reordering: f64,
+    #[serde(default)]
+    pub seed: u64,
+}
+
+fn default_announce_wait_secs() -> u64 {
+    60
+}
+
+fn default_max_packet_size() -> usize {
+    4096
+}
+
+fn default_test_players() -> usize {
+    2
+}
+
+fn default_test_host_handles() -> Vec<usize> {
+    vec![0]
+}
+
+#[derive(Clone, Debug, PartialOrd, PartialEq, Serialize, Deserialize, Default)]
+pub enum InputGeneratorConfig {
+    #[default]
+    Neutral,
+    Random(u64), //Seed
+    Mirror,
+    Replay(String), //Path to raw inputs, one per frame
+}
+
+//Which input a disconnected player gets, must be the same on every peer to stay deterministic
+#[derive(Clone, Copy, Debug, PartialOrd, PartialEq, Serialize, Deserialize, Default)]
+pub enum DisconnectedInputPolicy {
+    #[default]
+    Neutral,
+    RepeatLastConfirmed,
+    Host,
+}
+
+#[derive(Clone, Debug, PartialOrd, PartialEq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "PascalCase")]
+pub struct StateStorageConfig {
+    #[serde(default)]
+    pub compression: StateCompression,
+    pub keyframe_interval: Option<u32>, //Enables delta snapshots between keyframes
+}
+
+#[derive(Clone, Copy, Debug, PartialOrd, PartialEq, Serialize, Deserialize, Default)]
+pub enum StateCompression {
+    #[default]
+    None,
+    Deflate(u32), //Level, from 0 (fastest) to 9 (smallest), higher ones are clamped to 9
+}
+
+//Exchange needs the same setting on every peer, over direct transports a lost packet leaves the diff unfinished
+#[derive(Clone, Debug, PartialOrd, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DesyncDumpConfig {
+    pub directory: String,
+    #[serde(default)]
+    pub exchange: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialOrd, PartialEq, Serialize, Deserialize, Default)]
+pub enum ChecksumAlgorithm {
+    Xxh3_64,
+    #[default]
+    Xxh3_128,
 }
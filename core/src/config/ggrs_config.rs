@@ -1,10 +1,20 @@
 use core::fmt;
-use std::net::SocketAddr;
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
 
 use ggrs::{Config, Message, UdpNonBlockingSocket};
 use matchbox_socket::{Packet, PeerId, WebRtcChannel};
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+use tracing::{error, warn};
 
-use crate::model::{game_state::GameState, input::Input};
+use crate::{
+    core::{packet_cipher::PacketCipher, rendezvous},
+    model::{game_state::GameState, input::Input},
+};
 
 #[derive(Debug)]
 pub struct GGRSConfig;
@@ -18,6 +28,24 @@ impl Config for GGRSConfig {
 pub enum Address {
     Socket(SocketAddr),
     Peer(PeerId),
+    Loopback(u32),
+}
+
+#[derive(Hash, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Udp,
+    Matchbox,
+    Loopback,
+}
+
+impl Address {
+    pub fn transport(&self) -> Transport {
+        match self {
+            Address::Socket(_) => Transport::Udp,
+            Address::Peer(_) => Transport::Matchbox,
+            Address::Loopback(_) => Transport::Loopback,
+        }
+    }
 }
 
 impl fmt::Display for Address {
@@ -29,42 +57,471 @@ impl fmt::Display for Address {
             Address::Peer(peer_id) => {
                 write!(f, "{}", peer_id.0)
             }
+            Address::Loopback(id) => {
+                write!(f, "loopback:{}", id)
+            }
+        }
+    }
+}
+
+const DROPPED_PACKETS_WARNING_INTERVAL: u32 = 100;
+//Control packets share the session sockets with GGRS messages but never reach GGRS
+const CONTROL_MAGIC: &[u8; 8] = b"GGRSCTL1";
+
+fn build_packet(msg: &Message) -> Option<Packet> {
+    match bincode::serialize(&msg) {
+        Ok(bytes) => Some(bytes.into_boxed_slice()),
+        Err(e) => {
+            error!("Couldn't serialize message, dropped : {}", e);
+            None
+        }
+    }
+}
+
+#[derive(Default)]
+struct DroppedPackets {
+    per_peer: HashMap<Address, u32>,
+    warnings: Vec<String>,
+}
+
+//Byte after the magic, a peer's metadata replaces the one still queued while desync states queue up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlKind {
+    Metadata = 0,
+    DesyncState = 1,
+}
+
+impl ControlKind {
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(ControlKind::Metadata),
+            1 => Some(ControlKind::DesyncState),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct ControlPackets {
+    incoming: Vec<(Address, ControlKind, Vec<u8>)>,
+    outgoing: Vec<(Address, ControlKind, Vec<u8>)>,
+}
+
+//Decodes incoming packets, a malformed or oversized packet is dropped and counted for its peer
+#[derive(Clone, Default)]
+pub struct PacketGuard {
+    max_size: Option<usize>,
+    dropped: Arc<Mutex<DroppedPackets>>,
+    control: Arc<Mutex<ControlPackets>>,
+}
+
+impl PacketGuard {
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            max_size: Some(max_size),
+            ..Default::default()
+        }
+    }
+
+    pub fn decode(&self, address: &Address, packet: &[u8]) -> Option<Message> {
+        if let Some(max_size) = self.max_size {
+            if packet.len() > max_size {
+                self.reject(
+                    address,
+                    format!("{} bytes over the {} bytes limit", packet.len(), max_size),
+                );
+                return None;
+            }
+        }
+
+        if let Some(payload) = packet.strip_prefix(CONTROL_MAGIC) {
+            match payload.split_first() {
+                Some((kind, payload)) => match ControlKind::from_byte(*kind) {
+                    Some(kind) => self.control.lock().unwrap().incoming.push((
+                        address.clone(),
+                        kind,
+                        payload.to_vec(),
+                    )),
+                    None => self.reject(address, format!("Unknown control packet kind {}", kind)),
+                },
+                None => self.reject(address, "Empty control packet".to_string()),
+            }
+            return None;
+        }
+
+        match bincode::deserialize(packet) {
+            Ok(message) => Some(message),
+            Err(e) => {
+                self.reject(address, e.to_string());
+                None
+            }
+        }
+    }
+
+    //Queued until the socket reaching the address polls, it goes out like a GGRS message
+    pub fn post_control(&self, address: &Address, kind: ControlKind, payload: &[u8]) {
+        let mut packet = CONTROL_MAGIC.to_vec();
+        packet.push(kind as u8);
+        packet.extend_from_slice(payload);
+
+        let mut control = self.control.lock().unwrap();
+
+        if kind == ControlKind::Metadata {
+            control
+                .outgoing
+                .retain(|(queued, queued_kind, _)| queued != address || *queued_kind != kind);
+        }
+
+        control.outgoing.push((address.clone(), kind, packet));
+    }
+
+    pub fn take_control(&self, kind: ControlKind) -> Vec<(Address, Vec<u8>)> {
+        let mut control = self.control.lock().unwrap();
+        let (taken, others): (Vec<_>, Vec<_>) = control
+            .incoming
+            .drain(..)
+            .partition(|(_, incoming_kind, _)| *incoming_kind == kind);
+
+        control.incoming = others;
+        taken
+            .into_iter()
+            .map(|(address, _, payload)| (address, payload))
+            .collect()
+    }
+
+    pub(crate) fn take_outgoing_control(&self, transport: Transport) -> Vec<(Address, Vec<u8>)> {
+        let mut control = self.control.lock().unwrap();
+        let (outgoing, others): (Vec<_>, Vec<_>) = control
+            .outgoing
+            .drain(..)
+            .partition(|(address, _, _)| address.transport() == transport);
+
+        control.outgoing = others;
+        outgoing
+            .into_iter()
+            .map(|(address, _, packet)| (address, packet))
+            .collect()
+    }
+
+    pub fn dropped(&self, address: &Address) -> u32 {
+        let dropped = self.dropped.lock().unwrap();
+
+        dropped.per_peer.get(address).copied().unwrap_or(0)
+    }
+
+    pub fn take_warnings(&self) -> Vec<String> {
+        self.dropped.lock().unwrap().warnings.drain(..).collect()
+    }
+
+    pub fn reject(&self, address: &Address, reason: String) {
+        let mut dropped = self.dropped.lock().unwrap();
+        let count = dropped.per_peer.entry(address.clone()).or_default();
+
+        *count += 1;
+
+        //Warn on the first one then periodically, a broken peer can send a lot of them
+        if *count == 1 || count.is_multiple_of(DROPPED_PACKETS_WARNING_INTERVAL) {
+            let warning = format!(
+                "PacketDropped from {} : {} ({} dropped)",
+                address, reason, count
+            );
+
+            warn!("{}", warning);
+            dropped.warnings.push(warning);
         }
     }
 }
 
-fn build_packet(msg: &Message) -> Packet {
-    bincode::serialize(&msg).unwrap().into_boxed_slice()
+pub struct PeerChannel {
+    channel: WebRtcChannel,
+    guard: PacketGuard,
 }
 
-fn deserialize_packet(message: (PeerId, Packet)) -> (PeerId, Message) {
-    (message.0, bincode::deserialize(&message.1).unwrap())
+impl PeerChannel {
+    pub fn new(channel: WebRtcChannel, guard: PacketGuard) -> Self {
+        Self { channel, guard }
+    }
 }
 
-impl ggrs::NonBlockingSocket<Address> for WebRtcChannel {
+impl ggrs::NonBlockingSocket<Address> for PeerChannel {
     fn send_to(&mut self, msg: &Message, addr: &Address) {
         match addr {
-            Address::Socket(_) => panic!("Cannot send to socket address, use a peer id instead"),
-            Address::Peer(peer_id) => self.send(build_packet(msg), *peer_id),
+            Address::Peer(peer_id) => {
+                if let Some(packet) = build_packet(msg) {
+                    self.channel.send(packet, *peer_id)
+                }
+            }
+            _ => error!("Cannot send to {} over matchbox, message dropped", addr),
         }
     }
 
     fn receive_all_messages(&mut self) -> Vec<(Address, Message)> {
-        self.receive()
+        for (address, packet) in self.guard.take_outgoing_control(Transport::Matchbox) {
+            if let Address::Peer(peer_id) = address {
+                self.channel.send(packet.into_boxed_slice(), peer_id);
+            }
+        }
+
+        self.channel
+            .receive()
             .into_iter()
-            .map(deserialize_packet)
-            .map(|x| (Address::Peer(x.0), x.1))
+            .filter_map(|(peer_id, packet)| {
+                let address = Address::Peer(peer_id);
+
+                self.guard
+                    .decode(&address, &packet)
+                    .map(|message| (address, message))
+            })
             .collect::<Vec<(Address, Message)>>()
     }
 }
 
+const RECV_BUFFER_SIZE: usize = 65536;
+
+//Direct UDP socket decoding through a PacketGuard, sealing packets when a pre-shared key is set
+pub struct DirectUdpSocket {
+    socket: UdpSocket,
+    guard: PacketGuard,
+    cipher: Option<PacketCipher>,
+    buffer: Vec<u8>,
+    is_ipv6: bool,
+    reaches_ipv4: bool, //IPv4 sockets, and IPv6 ones accepting mapped addresses
+}
+
+impl DirectUdpSocket {
+    //Without bind address the socket is dual stack, or IPv4 only when the system has no IPv6.
+    //IPv6 sockets never default to v6 only, Windows would otherwise drop mapped IPv4 peers
+    pub fn bind(bind_addr: Option<&str>, port: u16) -> Result<UdpSocket, String> {
+        match bind_addr {
+            Some(bind_addr) => {
+                let ip = bind_addr
+                    .parse::<IpAddr>()
+                    .map_err(|e| format!("Can't parse bind addr {} : {}", bind_addr, e))?;
+
+                match ip {
+                    IpAddr::V4(_) => UdpSocket::bind((ip, port)),
+                    IpAddr::V6(v6) => bind_ipv6(v6, port),
+                }
+                .map_err(|e| format!("Couldn't bind UDP {}:{} : {}", ip, port, e))
+            }
+            None => bind_ipv6(Ipv6Addr::UNSPECIFIED, port).or_else(|e| {
+                warn!("No dual stack UDP socket ({}), binding IPv4 only", e);
+
+                UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port))
+                    .map_err(|e| format!("Couldn't bind UDP port {} : {}", port, e))
+            }),
+        }
+    }
+
+    pub fn new(
+        socket: UdpSocket,
+        guard: PacketGuard,
+        cipher: Option<PacketCipher>,
+    ) -> Result<Self, String> {
+        socket
+            .set_nonblocking(true)
+            .map_err(|e| format!("Couldn't set UDP socket non blocking : {}", e))?;
+
+        let local_addr = socket
+            .local_addr()
+            .map_err(|e| format!("Couldn't read UDP socket address : {}", e))?;
+        let is_ipv6 = local_addr.is_ipv6();
+        //The socket may come from the caller, only a wildcard one not v6 only sends to mapped addresses
+        let reaches_ipv4 = match local_addr.ip() {
+            IpAddr::V4(_) => true,
+            IpAddr::V6(ip) => {
+                ip.is_unspecified() && !SockRef::from(&socket).only_v6().unwrap_or(true)
+            }
+        };
+
+        Ok(Self {
+            socket,
+            guard,
+            cipher,
+            buffer: vec![0; RECV_BUFFER_SIZE],
+            is_ipv6,
+            reaches_ipv4,
+        })
+    }
+
+    pub fn bind_to_port(
+        port: u16,
+        guard: PacketGuard,
+        cipher: Option<PacketCipher>,
+    ) -> Result<Self, String> {
+        Self::new(Self::bind(None, port)?, guard, cipher)
+    }
+
+    pub fn local_port(&self) -> Option<u16> {
+        self.socket.local_addr().ok().map(|addr| addr.port())
+    }
+
+    pub fn send_raw(&self, packet: &[u8], addr: &SocketAddr) -> std::io::Result<usize> {
+        let target = match (addr, self.is_ipv6) {
+            (SocketAddr::V4(v4), true) => SocketAddr::from((v4.ip().to_ipv6_mapped(), v4.port())),
+            _ => *addr,
+        };
+
+        self.socket.send_to(packet, target)
+    }
+
+    pub fn receive_raw(&mut self) -> Vec<(SocketAddr, Vec<u8>)> {
+        let mut packets = vec![];
+
+        loop {
+            match self.socket.recv_from(&mut self.buffer) {
+                Ok((size, addr)) => packets.push((normalize(addr), self.buffer[..size].to_vec())),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                //Windows reports ICMP port unreachable on the next receive, the peer may not be up yet
+                Err(ref e) if e.kind() == ErrorKind::ConnectionReset => continue,
+                Err(e) => {
+                    warn!("Couldn't receive UDP packet : {}", e);
+                    break;
+                }
+            }
+        }
+
+        packets
+    }
+
+    fn send_sealed(&mut self, packet: Vec<u8>, addr: &SocketAddr) {
+        let packet = match self.cipher.as_mut() {
+            Some(cipher) => match cipher.seal(&packet) {
+                Ok(sealed) => sealed,
+                Err(e) => {
+                    error!("{}, message dropped", e);
+                    return;
+                }
+            },
+            None => packet,
+        };
+
+        if let Err(e) = self.send_raw(&packet, addr) {
+            warn!("Couldn't send UDP packet to {} : {}", addr, e);
+        }
+    }
+
+    //Handshakes with peers are sealed like session packets when there is a pre shared key
+    pub fn send_handshake(&mut self, packet: Vec<u8>, addr: &SocketAddr) {
+        self.send_sealed(packet, addr);
+    }
+
+    //None when the packet fails authentication
+    pub fn open_handshake(&mut self, addr: &SocketAddr, packet: Vec<u8>) -> Option<Vec<u8>> {
+        match self.cipher.as_mut() {
+            Some(cipher) => cipher.open(&Address::Socket(*addr), &packet).ok(),
+            None => Some(packet),
+        }
+    }
+
+    //Hostnames are resolved to an address of a family the socket can reach, IPv4 first
+    pub fn resolve(&self, remote_addr: &str) -> Result<SocketAddr, String> {
+        resolve(remote_addr, self.reaches_ipv4, self.is_ipv6)
+    }
+}
+
+pub fn resolve(
+    remote_addr: &str,
+    allow_ipv4: bool,
+    allow_ipv6: bool,
+) -> Result<SocketAddr, String> {
+    let addrs = remote_addr
+        .to_socket_addrs()
+        .map_err(|e| format!("Can't resolve remote addr {} : {}", remote_addr, e))?
+        .map(normalize)
+        .collect::<Vec<SocketAddr>>();
+
+    addrs
+        .iter()
+        .find(|addr| addr.is_ipv4() && allow_ipv4)
+        .or_else(|| addrs.iter().find(|addr| addr.is_ipv6() && allow_ipv6))
+        .copied()
+        .ok_or(format!("No reachable address found for {}", remote_addr))
+}
+
+fn bind_ipv6(ip: Ipv6Addr, port: u16) -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+
+    socket.set_only_v6(false)?;
+    socket.bind(&SocketAddr::from((ip, port)).into())?;
+
+    Ok(socket.into())
+}
+
+//A dual stack socket sees IPv4 peers as mapped IPv6 addresses, sessions only know them as IPv4
+fn normalize(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+            Some(v4) => SocketAddr::from((v4, v6.port())),
+            None => addr,
+        },
+        SocketAddr::V4(_) => addr,
+    }
+}
+
+impl ggrs::NonBlockingSocket<Address> for DirectUdpSocket {
+    fn send_to(&mut self, msg: &Message, address: &Address) {
+        let addr = match address {
+            Address::Socket(addr) => addr,
+            _ => {
+                error!("Cannot send to {} over UDP, message dropped", address);
+                return;
+            }
+        };
+
+        if let Some(packet) = build_packet(msg) {
+            self.send_sealed(packet.into_vec(), addr);
+        }
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(Address, Message)> {
+        for (address, packet) in self.guard.take_outgoing_control(Transport::Udp) {
+            if let Address::Socket(addr) = address {
+                self.send_sealed(packet, &addr);
+            }
+        }
+
+        let mut messages = vec![];
+
+        for (addr, packet) in self.receive_raw() {
+            //Late hole punching packets can still arrive once the session started
+            if rendezvous::is_handshake(&packet) {
+                continue;
+            }
+
+            let address = Address::Socket(addr);
+
+            let opened = match self.cipher.as_mut() {
+                Some(cipher) => match cipher.open(&address, &packet) {
+                    Ok(opened) => opened,
+                    Err(e) => {
+                        self.guard.reject(&address, e);
+                        continue;
+                    }
+                },
+                None => packet,
+            };
+
+            //Sealed handshakes too
+            if rendezvous::is_handshake(&opened) {
+                continue;
+            }
+
+            if let Some(message) = self.guard.decode(&address, &opened) {
+                messages.push((address, message));
+            }
+        }
+
+        messages
+    }
+}
+
+//GGRS decodes UDP packets itself, sessions use DirectUdpSocket to have them guarded
 impl ggrs::NonBlockingSocket<Address> for UdpNonBlockingSocket {
     fn send_to(&mut self, msg: &Message, address: &Address) {
         match address {
             Address::Socket(addr) => self.send_to(msg, addr),
-            Address::Peer(_) => {
-                panic!("Cannot send to peer id, use a socket address instead")
-            }
+            _ => error!("Cannot send to {} over UDP, message dropped", address),
         }
     }
 
@@ -75,3 +532,143 @@ impl ggrs::NonBlockingSocket<Address> for UdpNonBlockingSocket {
             .collect::<Vec<(Address, Message)>>()
     }
 }
+
+type LoopbackEndpoints = Arc<Mutex<HashMap<u32, Sender<(u32, Packet)>>>>;
+
+//In-process network, every socket opened on it can reach the others by their loopback address
+#[derive(Clone, Default)]
+pub struct LoopbackNetwork {
+    endpoints: LoopbackEndpoints,
+    next_id: Arc<AtomicU32>,
+}
+
+impl LoopbackNetwork {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn socket(&self) -> LoopbackSocket {
+        let (sender, receiver) = channel();
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+
+        self.endpoints.lock().unwrap().insert(id, sender);
+
+        LoopbackSocket {
+            id,
+            network: self.clone(),
+            receiver: Mutex::new(receiver),
+            guard: PacketGuard::default(),
+        }
+    }
+}
+
+pub struct LoopbackSocket {
+    id: u32,
+    network: LoopbackNetwork,
+    receiver: Mutex<Receiver<(u32, Packet)>>,
+    guard: PacketGuard,
+}
+
+impl LoopbackSocket {
+    pub fn address(&self) -> Address {
+        Address::Loopback(self.id)
+    }
+
+    //The session guard, so control packets go through the loopback network too
+    pub fn with_guard(mut self, guard: PacketGuard) -> Self {
+        self.guard = guard;
+        self
+    }
+}
+
+impl Drop for LoopbackSocket {
+    fn drop(&mut self) {
+        if let Ok(mut endpoints) = self.network.endpoints.lock() {
+            endpoints.remove(&self.id);
+        }
+    }
+}
+
+impl ggrs::NonBlockingSocket<Address> for LoopbackSocket {
+    fn send_to(&mut self, msg: &Message, address: &Address) {
+        match address {
+            Address::Loopback(id) => {
+                //Messages go through bincode like they would on a real wire, unknown ids are lost packets
+                let endpoints = self.network.endpoints.lock().unwrap();
+
+                if let (Some(endpoint), Some(packet)) = (endpoints.get(id), build_packet(msg)) {
+                    let _ = endpoint.send((self.id, packet));
+                }
+            }
+            _ => error!("Cannot send to {} over loopback, message dropped", address),
+        }
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(Address, Message)> {
+        for (address, packet) in self.guard.take_outgoing_control(Transport::Loopback) {
+            if let Address::Loopback(id) = address {
+                let endpoints = self.network.endpoints.lock().unwrap();
+
+                if let Some(endpoint) = endpoints.get(&id) {
+                    let _ = endpoint.send((self.id, packet.into_boxed_slice()));
+                }
+            }
+        }
+
+        self.receiver
+            .lock()
+            .unwrap()
+            .try_iter()
+            .filter_map(|(id, packet)| {
+                let address = Address::Loopback(id);
+
+                self.guard
+                    .decode(&address, &packet)
+                    .map(|message| (address, message))
+            })
+            .collect::<Vec<(Address, Message)>>()
+    }
+}
+
+//Holds one socket per transport and routes every message by its address variant
+#[derive(Default)]
+pub struct CompositeSocket {
+    transports: Vec<(Transport, Box<dyn ggrs::NonBlockingSocket<Address>>)>,
+}
+
+impl CompositeSocket {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_transport<S: ggrs::NonBlockingSocket<Address> + 'static>(
+        mut self,
+        transport: Transport,
+        socket: S,
+    ) -> Self {
+        self.transports
+            .retain(|(existing, _)| *existing != transport);
+        self.transports.push((transport, Box::new(socket)));
+        self
+    }
+}
+
+impl ggrs::NonBlockingSocket<Address> for CompositeSocket {
+    fn send_to(&mut self, msg: &Message, addr: &Address) {
+        match self
+            .transports
+            .iter_mut()
+            .find(|(transport, _)| *transport == addr.transport())
+        {
+            Some((_, socket)) => socket.send_to(msg, addr),
+            None => error!("No transport to reach {}, message dropped", addr),
+        }
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(Address, Message)> {
+        self.transports
+            .iter_mut()
+            .flat_map(|(_, socket)| socket.receive_all_messages())
+            .collect::<Vec<(Address, Message)>>()
+    }
+}
@@ -0,0 +1,104 @@
+use xxhash_rust::xxh3::{xxh3_128, xxh3_64, Xxh3};
+
+use crate::{
+    config::app_config::ChecksumAlgorithm,
+    model::{byte_range::ByteRange, game_state::GameState},
+};
+
+//The top byte of every checksum tells which algorithm produced it, so peers configured
+//with different algorithms are told apart from a real desync
+const ALGORITHM_SHIFT: u32 = 120;
+const HASH_MASK: u128 = (1 << ALGORITHM_SHIFT) - 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChecksumSource {
+    Full,
+    Host(u128),
+    Ranges(Vec<ByteRange>),
+    Sections,
+}
+
+pub fn checksum(algorithm: ChecksumAlgorithm, data: &[u8]) -> u128 {
+    let hash = match algorithm {
+        ChecksumAlgorithm::Xxh3_64 => xxh3_64(data) as u128,
+        ChecksumAlgorithm::Xxh3_128 => xxh3_128(data),
+    };
+
+    tagged(algorithm, hash)
+}
+
+//Host checksums are untagged, algorithm_of returns None for them
+pub fn checksum_of(
+    algorithm: ChecksumAlgorithm,
+    game_state: &GameState,
+    source: &ChecksumSource,
+) -> Result<u128, String> {
+    let data = game_state.bytes();
+
+    match source {
+        ChecksumSource::Full => Ok(checksum(algorithm, data)),
+        ChecksumSource::Host(host_checksum) => Ok(host_checksum & HASH_MASK),
+        ChecksumSource::Ranges(ranges) => {
+            //No range would give every state the same checksum
+            if ranges.is_empty() {
+                return Err("No checksum range given".to_string());
+            }
+
+            let mut hasher = Xxh3::new();
+
+            for range in ranges {
+                match range.offset.checked_add(range.len) {
+                    Some(end) if end <= data.len() => hasher.update(&data[range.offset..end]),
+                    _ => {
+                        return Err(format!(
+                            "Checksum range {} (+{}) is out of a {} bytes game state",
+                            range.offset,
+                            range.len,
+                            data.len()
+                        ))
+                    }
+                }
+            }
+
+            let hash = match algorithm {
+                ChecksumAlgorithm::Xxh3_64 => hasher.digest() as u128,
+                ChecksumAlgorithm::Xxh3_128 => hasher.digest128(),
+            };
+
+            Ok(tagged(algorithm, hash))
+        }
+        //Every section checksum is already computed, combining them is enough
+        ChecksumSource::Sections => {
+            let mut hasher = Xxh3::new();
+
+            for section in game_state.sections() {
+                hasher.update(section.name.as_bytes());
+                hasher.update(&section.checksum.to_le_bytes());
+            }
+
+            let hash = match algorithm {
+                ChecksumAlgorithm::Xxh3_64 => hasher.digest() as u128,
+                ChecksumAlgorithm::Xxh3_128 => hasher.digest128(),
+            };
+
+            Ok(tagged(algorithm, hash))
+        }
+    }
+}
+
+pub fn algorithm_of(checksum: u128) -> Option<ChecksumAlgorithm> {
+    match checksum >> ALGORITHM_SHIFT {
+        1 => Some(ChecksumAlgorithm::Xxh3_64),
+        2 => Some(ChecksumAlgorithm::Xxh3_128),
+        _ => None,
+    }
+}
+
+fn tagged(algorithm: ChecksumAlgorithm, hash: u128) -> u128 {
+    let tag: u128 = match algorithm {
+        ChecksumAlgorithm::Xxh3_64 => 1,
+        ChecksumAlgorithm::Xxh3_128 => 2,
+    };
+
+    (hash & HASH_MASK) | (tag << ALGORITHM_SHIFT)
+}
@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+use std::thread::{sleep, JoinHandle};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, Socket, Type};
+use tracing::{info, warn};
+
+pub const DISCOVERY_PORT: u16 = 7400;
+const MULTICAST_GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 74, 0);
+const MAGIC: &[u8; 8] = b"GGRSDSC1";
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(1);
+const HOST_EXPIRATION: Duration = Duration::from_secs(5);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct HostAnnouncement {
+    pub name: String,
+    pub game_version: String,
+    pub num_players: u32,
+    pub free_slots: u32,
+    pub port: u16, //Session port, the host address is the one the announcement comes from
+    pub host_player_draw: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct DiscoveredHost {
+    pub addr: SocketAddr,
+    pub announcement: HostAnnouncement,
+}
+
+fn encode(announcement: &HostAnnouncement) -> Vec<u8> {
+    let mut packet = MAGIC.to_vec();
+    packet.extend(bincode::serialize(announcement).unwrap_or_default());
+    packet
+}
+
+fn decode(packet: &[u8]) -> Option<HostAnnouncement> {
+    match packet.starts_with(MAGIC) {
+        true => bincode::deserialize(&packet[MAGIC.len()..]).ok(),
+        false => None,
+    }
+}
+
+//Background thread, stopped when dropped
+struct Worker {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+    fn spawn<F: FnMut() + Send + 'static>(name: &str, mut tick: F) -> Result<Self, String> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let should_stop = stop.clone();
+
+        let handle = std::thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || {
+                while !should_stop.load(Ordering::Relaxed) {
+                    tick();
+                }
+            })
+            .map_err(|e| format!("Failed to spawn {} : {}", name, e))?;
+
+        Ok(Self {
+            stop,
+            handle: Some(handle),
+        })
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+//Broadcasts and multicasts the announcement on the LAN until dropped
+pub struct Announcer {
+    _worker: Worker,
+}
+
+impl Announcer {
+    pub fn start(announcement: HostAnnouncement) -> Result<Self, String> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+            .map_err(|e| format!("Couldn't bind announce socket : {}", e))?;
+
+        socket
+            .set_broadcast(true)
+            .map_err(|e| format!("Couldn't enable broadcast : {}", e))?;
+
+        let packet = encode(&announcement);
+        let targets = [
+            SocketAddr::from((Ipv4Addr::BROADCAST, DISCOVERY_PORT)),
+            SocketAddr::from((MULTICAST_GROUP, DISCOVERY_PORT)),
+        ];
+        let mut last_sent: Option<Instant> = None;
+
+        info!("Announcing {} on the LAN", announcement.name);
+
+        let worker = Worker::spawn("discovery-announcer", move || {
+            if last_sent.is_none_or(|last| last.elapsed() >= ANNOUNCE_INTERVAL) {
+                for target in targets.iter() {
+                    if let Err(e) = socket.send_to(&packet, target) {
+                        warn!("Couldn't announce to {} : {}", target, e);
+                    }
+                }
+
+                last_sent = Some(Instant::now());
+            }
+
+            sleep(POLL_INTERVAL);
+        })?;
+
+        Ok(Self { _worker: worker })
+    }
+}
+
+//Collects announcements from the LAN until dropped
+pub struct Listener {
+    hosts: Arc<Mutex<HashMap<SocketAddr, (HostAnnouncement, Instant)>>>,
+    _worker: Worker,
+}
+
+impl Listener {
+    pub fn start() -> Result<Self, String> {
+        let socket = bind_listener().map_err(|e| {
+            format!(
+                "Couldn't listen on discovery port {} : {}",
+                DISCOVERY_PORT, e
+            )
+        })?;
+
+        socket
+            .set_read_timeout(Some(POLL_INTERVAL))
+            .map_err(|e| format!("Couldn't set discovery socket timeout : {}", e))?;
+
+        let hosts: Arc<Mutex<HashMap<SocketAddr, (HostAnnouncement, Instant)>>> =
+            Arc::new(Mutex::new(HashMap::new()));
+        let shared_hosts = hosts.clone();
+        let mut buffer = vec![0; 2048];
+
+        let worker = Worker::spawn("discovery-listener", move || {
+            let (size, from) = match socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(_) => return,
+            };
+
+            if let Some(announcement) = decode(&buffer[..size]) {
+                //Broadcast and multicast copies of the same announcement land on the same entry
+                let addr = SocketAddr::new(from.ip(), announcement.port);
+
+                if let Ok(mut hosts) = shared_hosts.lock() {
+                    hosts.insert(addr, (announcement, Instant::now()));
+                }
+            }
+        })?;
+
+        Ok(Self {
+            hosts,
+            _worker: worker,
+        })
+    }
+
+    //Hosts heard from recently, sorted by address so indexes stay stable between calls
+    pub fn hosts(&self) -> Vec<DiscoveredHost> {
+        let mut hosts = self.hosts.lock().unwrap();
+
+        hosts.retain(|_, (_, last_seen)| last_seen.elapsed() < HOST_EXPIRATION);
+
+        let mut discovered: Vec<DiscoveredHost> = hosts
+            .iter()
+            .map(|(addr, (announcement, _))| DiscoveredHost {
+                addr: *addr,
+                announcement: announcement.clone(),
+            })
+            .collect();
+
+        discovered.sort_by_key(|host| host.addr);
+        discovered
+    }
+}
+
+//Several clients on the same machine can listen at once
+fn bind_listener() -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+
+    socket.set_reuse_address(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, DISCOVERY_PORT)).into())?;
+
+    let socket: UdpSocket = socket.into();
+
+    if let Err(e) = socket.join_multicast_v4(&MULTICAST_GROUP, &Ipv4Addr::UNSPECIFIED) {
+        warn!(
+            "Couldn't join discovery multicast group, broadcast only : {}",
+            e
+        );
+    }
+
+    Ok(socket)
+}
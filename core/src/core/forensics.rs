@@ -0,0 +1,337 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::app_config::{AppConfig, DesyncDumpConfig},
+    model::{
+        game_state::GameState,
+        input::Input,
+        state_section::{diverged_sections, StateSection},
+    },
+};
+
+const KEPT_CHECKED_STATES: usize = 4;
+const INPUT_HISTORY_DEPTH: i32 = 600;
+pub const CHUNK_SIZE: usize = 16 * 1024;
+pub const DIRECT_CHUNK_SIZE: usize = 1024; //Fits a control packet under the default max packet size
+const MAX_DIFF_RANGES: usize = 256;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateChunk {
+    pub frame: i32,
+    pub index: u32,
+    pub count: u32,
+    pub bytes: Vec<u8>,
+    pub sections: Vec<StateSection>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct DesyncInfo {
+    frame: i32,
+    local_checksum: String,
+    remote_checksum: String,
+    peer: String,
+    state_found: bool,
+    sections: Vec<StateSection>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DiffReport {
+    pub local_size: usize,
+    pub remote_size: usize,
+    pub first_difference: Option<usize>,
+    pub ranges: Vec<(usize, usize)>, //(offset, len)
+    pub truncated: bool,
+    pub diverged_sections: Vec<String>,
+}
+
+impl DiffReport {
+    pub fn new(local: &CheckedState, remote: &CheckedState) -> Self {
+        let (local, remote) = (&local.bytes, &remote.bytes);
+        let mut ranges: Vec<(usize, usize)> = vec![];
+        let mut truncated = false;
+        let longest = local.len().max(remote.len());
+
+        for offset in 0..longest {
+            if local.get(offset) == remote.get(offset) {
+                continue;
+            }
+
+            let extends_last = matches!(ranges.last(), Some((start, len)) if start + len == offset);
+
+            if extends_last {
+                if let Some((_, len)) = ranges.last_mut() {
+                    *len += 1;
+                }
+            } else if ranges.len() == MAX_DIFF_RANGES {
+                truncated = true;
+                break;
+            } else {
+                ranges.push((offset, 1));
+            }
+        }
+
+        Self {
+            local_size: local.len(),
+            remote_size: remote.len(),
+            first_difference: ranges.first().map(|(offset, _)| *offset),
+            ranges,
+            truncated,
+            diverged_sections: vec![],
+        }
+    }
+}
+
+pub struct CheckedState {
+    pub bytes: Vec<u8>,
+    pub sections: Vec<StateSection>,
+}
+
+//Keeps what is needed to explain a desync and writes it on disk when one happens
+pub struct Forensics {
+    directory: PathBuf,
+    exchange: bool,
+    interval: i32,
+    checked_states: BTreeMap<i32, CheckedState>,
+    inputs: BTreeMap<i32, Vec<Input>>,
+    incoming: HashMap<(String, i32), Vec<Option<Vec<u8>>>>,
+    last_desync_frame: Option<i32>,
+}
+
+impl Forensics {
+    pub fn new(config: &DesyncDumpConfig, interval: u32) -> Self {
+        Self {
+            directory: PathBuf::from(&config.directory),
+            exchange: config.exchange,
+            interval: interval.max(1) as i32,
+            checked_states: BTreeMap::new(),
+            inputs: BTreeMap::new(),
+            incoming: HashMap::new(),
+            last_desync_frame: None,
+        }
+    }
+
+    pub fn should_exchange(&self) -> bool {
+        self.exchange
+    }
+
+    //Only frames GGRS compares checksums for can be reported as desynced, GGRS 0.9 checks the one before each interval multiple
+    pub fn record_state(&mut self, frame: i32, game_state: &GameState) {
+        if (frame + 1) % self.interval != 0 {
+            return;
+        }
+
+        self.checked_states.insert(
+            frame,
+            CheckedState {
+                bytes: game_state.bytes().to_vec(),
+                sections: game_state.sections().to_vec(),
+            },
+        );
+
+        while self.checked_states.len() > KEPT_CHECKED_STATES {
+            self.checked_states.pop_first();
+        }
+    }
+
+    pub fn record_inputs(&mut self, frame: i32, inputs: &[Input]) {
+        self.inputs.insert(frame, inputs.to_vec());
+        self.inputs = self.inputs.split_off(&(frame - INPUT_HISTORY_DEPTH));
+    }
+
+    pub fn dump(
+        &mut self,
+        frame: i32,
+        local_checksum: u128,
+        remote_checksum: u128,
+        peer: &str,
+        config: &AppConfig,
+    ) -> Result<PathBuf, String> {
+        self.last_desync_frame = Some(self.last_desync_frame.map_or(frame, |last| last.max(frame)));
+
+        let dump_directory = self.dump_directory(frame);
+
+        fs::create_dir_all(&dump_directory)
+            .map_err(|e| format!("Couldn't create desync dump directory : {}", e))?;
+
+        let state = self.checked_states.get(&frame);
+
+        if let Some(state) = state {
+            write(dump_directory.join("local_state.bin"), &state.bytes)?;
+        }
+
+        let info = DesyncInfo {
+            frame,
+            local_checksum: format!("{:032x}", local_checksum),
+            remote_checksum: format!("{:032x}", remote_checksum),
+            peer: peer.to_string(),
+            state_found: state.is_some(),
+            sections: state.map(|s| s.sections.clone()).unwrap_or_default(),
+        };
+
+        write_json(dump_directory.join("desync.json"), &info)?;
+        write_json(dump_directory.join("inputs.json"), &self.inputs)?;
+        write_json(dump_directory.join("config.json"), config)?;
+
+        Ok(dump_directory)
+    }
+
+    pub fn chunks(&self, frame: i32, chunk_size: usize) -> Vec<StateChunk> {
+        match self.checked_states.get(&frame) {
+            Some(state) => {
+                let count = state.bytes.chunks(chunk_size).count().max(1) as u32;
+
+                if state.bytes.is_empty() {
+                    return vec![StateChunk {
+                        frame,
+                        index: 0,
+                        count,
+                        bytes: vec![],
+                        sections: state.sections.clone(),
+                    }];
+                }
+
+                state
+                    .bytes
+                    .chunks(chunk_size)
+                    .enumerate()
+                    .map(|(index, bytes)| StateChunk {
+                        frame,
+                        index: index as u32,
+                        count,
+                        bytes: bytes.to_vec(),
+                        sections: state.sections.clone(),
+                    })
+                    .collect()
+            }
+            None => vec![],
+        }
+    }
+
+    //Returns the diff report once every chunk of a peer state has been received
+    pub fn receive_chunk(
+        &mut self,
+        peer: &str,
+        chunk: StateChunk,
+    ) -> Option<Result<DiffReport, String>> {
+        if chunk.count == 0 || chunk.index >= chunk.count {
+            return Some(Err(format!("Invalid desync state chunk from {}", peer)));
+        }
+
+        //States of older desyncs will never complete
+        if let Some(last_desync_frame) = self.last_desync_frame {
+            self.incoming
+                .retain(|(_, frame), _| *frame >= last_desync_frame);
+
+            if chunk.frame < last_desync_frame {
+                return Some(Err(format!(
+                    "Stale desync state chunk from {} for frame {}",
+                    peer, chunk.frame
+                )));
+            }
+        }
+
+        //The count comes from the peer, it can't ask for more than our own state would need in the smallest chunks
+        let max_count = match self.checked_states.get(&chunk.frame) {
+            Some(local) => local.bytes.len() / DIRECT_CHUNK_SIZE + 1,
+            None => {
+                return Some(Err(format!(
+                    "No local state kept for frame {} to compare with {}",
+                    chunk.frame, peer
+                )))
+            }
+        };
+
+        if chunk.count as usize > max_count {
+            return Some(Err(format!(
+                "Desync state from {} announces {} chunks, at most {} expected",
+                peer, chunk.count, max_count
+            )));
+        }
+
+        let key = (peer.to_string(), chunk.frame);
+        let parts = self
+            .incoming
+            .entry(key.clone())
+            .or_insert_with(|| vec![None; chunk.count as usize]);
+
+        if parts.len() != chunk.count as usize {
+            self.incoming.remove(&key);
+            return Some(Err(format!(
+                "Inconsistent desync state chunks from {}",
+                peer
+            )));
+        }
+
+        parts[chunk.index as usize] = Some(chunk.bytes);
+
+        if parts.iter().any(|part| part.is_none()) {
+            return None;
+        }
+
+        let remote = CheckedState {
+            bytes: self
+                .incoming
+                .remove(&key)
+                .unwrap_or_default()
+                .into_iter()
+                .flatten()
+                .flatten()
+                .collect(),
+            sections: chunk.sections,
+        };
+
+        Some(self.write_remote_state(peer, chunk.frame, &remote))
+    }
+
+    fn write_remote_state(
+        &self,
+        peer: &str,
+        frame: i32,
+        remote: &CheckedState,
+    ) -> Result<DiffReport, String> {
+        let dump_directory = self.dump_directory(frame);
+
+        fs::create_dir_all(&dump_directory)
+            .map_err(|e| format!("Couldn't create desync dump directory : {}", e))?;
+
+        //Socket and loopback addresses hold a colon, not allowed in Windows file names
+        let peer = peer.replace(':', "_");
+
+        write(
+            dump_directory.join(format!("remote_state_{}.bin", peer)),
+            &remote.bytes,
+        )?;
+
+        let local = match self.checked_states.get(&frame) {
+            Some(local) => local,
+            None => return Err(format!("No local state kept for frame {}", frame)),
+        };
+
+        let mut report = DiffReport::new(local, remote);
+        report.diverged_sections = diverged_sections(&local.sections, &remote.sections);
+        write_json(dump_directory.join(format!("diff_{}.json", peer)), &report)?;
+
+        Ok(report)
+    }
+
+    fn dump_directory(&self, frame: i32) -> PathBuf {
+        self.directory.join(format!("desync_{}", frame))
+    }
+}
+
+fn write(path: PathBuf, bytes: &[u8]) -> Result<(), String> {
+    fs::write(&path, bytes).map_err(|e| format!("Couldn't write {} : {}", path.display(), e))
+}
+
+fn write_json<T: Serialize + ?Sized>(path: PathBuf, value: &T) -> Result<(), String> {
+    let json = serde_json::to_vec_pretty(value)
+        .map_err(|e| format!("Couldn't serialize {} : {}", path.display(), e))?;
+
+    write(path, &json)
+}
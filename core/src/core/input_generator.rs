@@ -0,0 +1,78 @@
+use std::{fs, mem::size_of};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{config::app_config::InputGeneratorConfig, model::input::Input};
+
+const MAX_RANDOM_HOLD_FRAMES: u32 = 30;
+
+//Feeds the handles that don't get real host input in a SyncTest session
+#[derive(Default)]
+#[allow(clippy::large_enum_variant)]
+pub enum InputGenerator {
+    #[default]
+    Neutral,
+    Random {
+        rng: StdRng,
+        current: Input,
+        remaining: u32,
+    },
+    Mirror,
+    Replay(Vec<Input>),
+}
+
+impl InputGenerator {
+    //Each generated handle gets its own random stream out of the same seed
+    pub fn new(config: &InputGeneratorConfig, handle: usize) -> Result<Self, String> {
+        match config {
+            InputGeneratorConfig::Neutral => Ok(InputGenerator::Neutral),
+            InputGeneratorConfig::Random(seed) => Ok(InputGenerator::Random {
+                rng: StdRng::seed_from_u64(seed.wrapping_add(handle as u64)),
+                current: Input::default(),
+                remaining: 0,
+            }),
+            InputGeneratorConfig::Mirror => Ok(InputGenerator::Mirror),
+            InputGeneratorConfig::Replay(path) => {
+                let bytes = fs::read(path)
+                    .map_err(|e| format!("Couldn't read input replay {} : {}", path, e))?;
+
+                if bytes.is_empty() || bytes.len() % size_of::<Input>() != 0 {
+                    return Err(format!(
+                        "Input replay {} is not a list of {} bytes inputs",
+                        path,
+                        size_of::<Input>()
+                    ));
+                }
+
+                let inputs = bytes
+                    .chunks_exact(size_of::<Input>())
+                    .map(bytemuck::pod_read_unaligned::<Input>)
+                    .collect();
+
+                Ok(InputGenerator::Replay(inputs))
+            }
+        }
+    }
+
+    pub fn next(&mut self, frame: i32, local_input: Input) -> Input {
+        match self {
+            InputGenerator::Neutral => Input::default(),
+            InputGenerator::Random {
+                rng,
+                current,
+                remaining,
+            } => {
+                //Hold each random input for a while, pressing something new every frame isn't how anybody plays
+                if *remaining == 0 {
+                    *current = Input::random(rng);
+                    *remaining = rng.gen_range(1..=MAX_RANDOM_HOLD_FRAMES);
+                }
+
+                *remaining -= 1;
+                *current
+            }
+            InputGenerator::Mirror => local_input,
+            InputGenerator::Replay(inputs) => inputs[frame.max(0) as usize % inputs.len()],
+        }
+    }
+}
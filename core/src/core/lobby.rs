@@ -0,0 +1,491 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::{select, FutureExt};
+use futures_timer::Delay;
+use matchbox_socket::{
+    ChannelConfig, MessageLoopFuture, MultipleChannels, PeerId, WebRtcChannel, WebRtcSocket,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::{
+    config::app_config::PlayerMetadata, set_netplay_disconnected, SHOULD_STOP_MATCHBOX_FUTURE,
+};
+
+const GGRS_CHANNEL: usize = 0;
+const LOBBY_CHANNEL: usize = 1;
+const FORENSICS_CHANNEL: usize = 2;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum LobbyRole {
+    Unassigned,
+    Player(usize),
+    Spectator,
+}
+
+#[derive(Debug, Clone)]
+pub struct LobbyPeer {
+    pub id: PeerId,
+    pub metadata: PlayerMetadata,
+    pub ready: bool,
+    pub role: LobbyRole,
+    pub is_local: bool,
+}
+
+//Peer ids travel as u128, the uuid crate is built without serde
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum LobbyMessage {
+    Hello {
+        metadata: PlayerMetadata,
+        ready: bool,
+    },
+    Ready(bool),
+    Roles(Vec<(u128, LobbyRole)>),
+    Start(Vec<(u128, LobbyRole)>),
+}
+
+//Written by the matchbox thread, read by the lobby
+#[derive(Default)]
+struct Connection {
+    id: Option<PeerId>,
+    peers: Vec<PeerId>,
+}
+
+//Matchbox room where peers gather, get their slots from the host and wait for it to start the session
+pub struct Lobby {
+    is_host: bool,
+    metadata: PlayerMetadata,
+    ready: bool,
+    connection: Arc<Mutex<Connection>>,
+    ggrs_channel: Option<WebRtcChannel>,
+    lobby_channel: WebRtcChannel,
+    forensics_channel: Option<WebRtcChannel>,
+    peers: Vec<LobbyPeer>,
+    roles: HashMap<PeerId, LobbyRole>,
+    host: Option<PeerId>,
+    started: bool,
+}
+
+impl Lobby {
+    pub fn open(
+        room_url: String,
+        is_host: bool,
+        metadata: PlayerMetadata,
+        exchange_desync_states: bool,
+    ) -> Result<Self, String> {
+        let mut builder = WebRtcSocket::builder(room_url)
+            .add_channel(ChannelConfig::unreliable())
+            .add_channel(ChannelConfig::reliable());
+
+        if exchange_desync_states {
+            builder = builder.add_channel(ChannelConfig::reliable());
+        }
+
+        let (mut socket, future_msg) = builder.build();
+
+        let ggrs_channel = socket
+            .take_channel(GGRS_CHANNEL)
+            .map_err(|e| format!("Couldn't take ggrs channel : {:?}", e))?;
+        let lobby_channel = socket
+            .take_channel(LOBBY_CHANNEL)
+            .map_err(|e| format!("Couldn't take lobby channel : {:?}", e))?;
+        let forensics_channel = match exchange_desync_states {
+            true => socket.take_channel(FORENSICS_CHANNEL).ok(),
+            false => None,
+        };
+
+        let connection = Arc::new(Mutex::new(Connection::default()));
+
+        spawn_matchbox_thread(socket, future_msg, connection.clone())?;
+
+        Ok(Self {
+            is_host,
+            metadata,
+            ready: false,
+            connection,
+            ggrs_channel: Some(ggrs_channel),
+            lobby_channel,
+            forensics_channel,
+            peers: vec![],
+            roles: HashMap::new(),
+            host: None,
+            started: false,
+        })
+    }
+
+    pub fn is_host(&self) -> bool {
+        self.is_host
+    }
+
+    pub fn local_id(&self) -> Option<PeerId> {
+        self.connection.lock().unwrap().id
+    }
+
+    //Peer the roles came from, the local one when hosting
+    pub fn host(&self) -> Option<PeerId> {
+        match self.is_host {
+            true => self.local_id(),
+            false => self.host,
+        }
+    }
+
+    //Tracks peers joining and leaving then handles their messages, returns the events to forward
+    pub fn update(&mut self) -> Vec<&'static str> {
+        let mut events = vec![];
+        let mut updated = false;
+        let (local_id, connected) = {
+            let connection = self.connection.lock().unwrap();
+            (connection.id, connection.peers.clone())
+        };
+
+        let before = self.peers.len();
+        self.peers.retain(|peer| connected.contains(&peer.id));
+        self.roles
+            .retain(|id, _| connected.contains(id) || Some(*id) == local_id);
+
+        if self.peers.len() != before {
+            events.push("LobbyPeerLeft");
+
+            if self.is_host {
+                self.broadcast_roles();
+            }
+        }
+
+        for id in connected {
+            if self.peers.iter().any(|peer| peer.id == id) {
+                continue;
+            }
+
+            info!("{} joined the lobby", id.0);
+
+            self.peers.push(LobbyPeer {
+                id,
+                metadata: PlayerMetadata::default(),
+                ready: false,
+                role: LobbyRole::Unassigned,
+                is_local: false,
+            });
+            self.send(
+                &LobbyMessage::Hello {
+                    metadata: self.metadata.clone(),
+                    ready: self.ready,
+                },
+                id,
+            );
+
+            if self.is_host {
+                self.send(&LobbyMessage::Roles(self.wire_roles()), id);
+            }
+
+            if !events.contains(&"LobbyPeerJoined") {
+                events.push("LobbyPeerJoined");
+            }
+        }
+
+        for (sender, packet) in self.lobby_channel.receive() {
+            let message: LobbyMessage = match bincode::deserialize(&packet) {
+                Ok(message) => message,
+                Err(e) => {
+                    warn!("Dropped lobby message from {} : {}", sender.0, e);
+                    continue;
+                }
+            };
+
+            match message {
+                LobbyMessage::Hello { metadata, ready } => {
+                    if let Some(peer) = self.peers.iter_mut().find(|peer| peer.id == sender) {
+                        peer.metadata = metadata;
+                        peer.ready = ready;
+                    }
+                }
+                LobbyMessage::Ready(ready) => {
+                    if let Some(peer) = self.peers.iter_mut().find(|peer| peer.id == sender) {
+                        peer.ready = ready;
+                    }
+                }
+                LobbyMessage::Roles(roles) | LobbyMessage::Start(roles) if self.is_host => {
+                    warn!(
+                        "{} sent roles to the host, ignored ({} roles)",
+                        sender.0,
+                        roles.len()
+                    );
+                    continue;
+                }
+                LobbyMessage::Roles(roles) => {
+                    self.host = Some(sender);
+                    self.roles = from_wire(roles);
+                }
+                LobbyMessage::Start(roles) => {
+                    self.host = Some(sender);
+                    self.roles = from_wire(roles);
+                    self.started = true;
+
+                    events.push("LobbyStarted");
+                }
+            }
+
+            updated = true;
+        }
+
+        for peer in self.peers.iter_mut() {
+            peer.role = self
+                .roles
+                .get(&peer.id)
+                .copied()
+                .unwrap_or(LobbyRole::Unassigned);
+        }
+
+        if updated {
+            events.push("LobbyUpdated");
+        }
+
+        events
+    }
+
+    //Remote peers plus the local one once the signaling server assigned its id
+    pub fn peers(&self) -> Vec<LobbyPeer> {
+        let mut peers = self.peers.clone();
+
+        if let Some(id) = self.local_id() {
+            peers.insert(
+                0,
+                LobbyPeer {
+                    id,
+                    metadata: self.metadata.clone(),
+                    ready: self.ready,
+                    role: self
+                        .roles
+                        .get(&id)
+                        .copied()
+                        .unwrap_or(LobbyRole::Unassigned),
+                    is_local: true,
+                },
+            );
+        }
+
+        peers
+    }
+
+    pub fn set_ready(&mut self, ready: bool) {
+        self.ready = ready;
+
+        for id in self.peer_ids() {
+            self.send(&LobbyMessage::Ready(ready), id);
+        }
+    }
+
+    pub fn assign(
+        &mut self,
+        peer: &str,
+        role: LobbyRole,
+        num_players: usize,
+    ) -> Result<(), String> {
+        if !self.is_host {
+            return Err("Only the host assigns slots".to_string());
+        }
+
+        let id =
+            PeerId(Uuid::parse_str(peer).map_err(|e| format!("Invalid peer {} : {}", peer, e))?);
+
+        if Some(id) != self.local_id() && !self.peer_ids().contains(&id) {
+            return Err(format!("{} isn't in the lobby", peer));
+        }
+
+        if let LobbyRole::Player(handle) = role {
+            if handle >= num_players {
+                return Err(format!(
+                    "Slot {} is out of a {} players session",
+                    handle, num_players
+                ));
+            }
+
+            //The slot moves to the new peer
+            self.roles
+                .retain(|other, other_role| *other == id || *other_role != role);
+        }
+
+        self.roles.insert(id, role);
+        self.broadcast_roles();
+
+        Ok(())
+    }
+
+    //Host side checks every slot is filled by a ready peer, then tells everyone to start
+    pub fn start(&mut self, num_players: usize) -> Result<Vec<(PeerId, LobbyRole)>, String> {
+        if !self.is_host {
+            return match self.started {
+                true => Ok(self.sorted_roles()),
+                false => Err("Waiting for the host to start".to_string()),
+            };
+        }
+
+        let local_id = self
+            .local_id()
+            .ok_or("Not connected to the signaling server")?;
+
+        //Spectators watch the host, it has to play
+        if !matches!(self.roles.get(&local_id), Some(LobbyRole::Player(_))) {
+            return Err("The host needs a player slot to start".to_string());
+        }
+
+        for handle in 0..num_players {
+            let id = self
+                .roles
+                .iter()
+                .find(|(_, role)| **role == LobbyRole::Player(handle))
+                .map(|(id, _)| *id)
+                .ok_or(format!("Slot {} isn't assigned", handle))?;
+
+            let ready = match id == local_id {
+                true => self.ready,
+                false => self
+                    .peers
+                    .iter()
+                    .find(|peer| peer.id == id)
+                    .is_some_and(|peer| peer.ready),
+            };
+
+            if !ready {
+                return Err(format!("Player {} isn't ready", handle));
+            }
+        }
+
+        let roles = self.wire_roles();
+
+        for id in self.peer_ids() {
+            self.send(&LobbyMessage::Start(roles.clone()), id);
+        }
+
+        self.started = true;
+
+        Ok(self.sorted_roles())
+    }
+
+    pub fn take_ggrs_channel(&mut self) -> Option<WebRtcChannel> {
+        self.ggrs_channel.take()
+    }
+
+    pub fn take_forensics_channel(&mut self) -> Option<WebRtcChannel> {
+        self.forensics_channel.take()
+    }
+
+    fn peer_ids(&self) -> Vec<PeerId> {
+        self.peers.iter().map(|peer| peer.id).collect()
+    }
+
+    fn sorted_roles(&self) -> Vec<(PeerId, LobbyRole)> {
+        let mut roles: Vec<(PeerId, LobbyRole)> =
+            self.roles.iter().map(|(id, role)| (*id, *role)).collect();
+
+        roles.sort_by_key(|(id, _)| id.0);
+        roles
+    }
+
+    fn wire_roles(&self) -> Vec<(u128, LobbyRole)> {
+        self.sorted_roles()
+            .into_iter()
+            .map(|(id, role)| (id.0.as_u128(), role))
+            .collect()
+    }
+
+    fn broadcast_roles(&mut self) {
+        let roles = self.wire_roles();
+
+        for id in self.peer_ids() {
+            self.send(&LobbyMessage::Roles(roles.clone()), id);
+        }
+    }
+
+    fn send(&mut self, message: &LobbyMessage, peer: PeerId) {
+        match bincode::serialize(message) {
+            Ok(packet) => self.lobby_channel.send(packet.into_boxed_slice(), peer),
+            Err(e) => error!("Couldn't serialize lobby message : {}", e),
+        }
+    }
+}
+
+fn from_wire(roles: Vec<(u128, LobbyRole)>) -> HashMap<PeerId, LobbyRole> {
+    roles
+        .into_iter()
+        .map(|(id, role)| (PeerId(Uuid::from_u128(id)), role))
+        .collect()
+}
+
+//Drives the socket until SHOULD_STOP_MATCHBOX_FUTURE is set, peers keep being tracked since they may come and go
+fn spawn_matchbox_thread(
+    mut socket: WebRtcSocket<MultipleChannels>,
+    future_msg: MessageLoopFuture,
+    connection: Arc<Mutex<Connection>>,
+) -> Result<(), String> {
+    {
+        let mut stp = SHOULD_STOP_MATCHBOX_FUTURE.lock().unwrap();
+        *stp = false;
+    }
+
+    set_netplay_disconnected(false);
+
+    let handle = std::thread::Builder::new()
+        .name("matchbox-thread-lobby".to_string())
+        .spawn(move || {
+            let rt = tokio::runtime::Runtime::new().unwrap();
+
+            rt.block_on(async {
+                let loop_fut = async {
+                    match future_msg.await {
+                        Ok(()) => info!("Lobby matchbox thread exited cleanly!"),
+                        Err(e) => match e {
+                            matchbox_socket::Error::Signaling(e) => {
+                                error!("Signaling error: {}", e);
+                            }
+                        },
+                    }
+                }
+                .fuse();
+
+                futures::pin_mut!(loop_fut);
+
+                let timeout = Delay::new(Duration::from_millis(5));
+                futures::pin_mut!(timeout);
+
+                let mut should_stop = false;
+
+                while !should_stop {
+                    if let Ok(stp) = SHOULD_STOP_MATCHBOX_FUTURE.try_lock() {
+                        should_stop = *stp;
+                    }
+
+                    socket.update_peers();
+
+                    if let Ok(mut connection) = connection.lock() {
+                        connection.id = socket.id();
+                        connection.peers = socket.connected_peers().collect();
+                    }
+
+                    select! {
+                        _ = (&mut timeout).fuse() => {
+                            timeout.reset(Duration::from_millis(10));
+                        }
+
+                        _ = &mut loop_fut => {
+                            info!("Lobby matchbox message loop ended!");
+
+                            if let Ok(mut stp) = SHOULD_STOP_MATCHBOX_FUTURE.lock() {
+                                *stp = true;
+                            }
+
+                            set_netplay_disconnected(true);
+                            break;
+                        }
+                    }
+                }
+            });
+        });
+
+    match handle {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("Failed to spawn lobby matchbox thread : {}", e)),
+    }
+}
@@ -1,2 +1,13 @@
 pub mod action_result;
+pub mod checksum;
+pub mod discovery;
+pub mod forensics;
+pub mod input_generator;
+pub mod lobby;
+pub mod network_simulator;
+pub mod packet_cipher;
+pub mod player_registry;
+pub mod rendezvous;
+pub mod state_store;
+pub mod synctest;
 pub mod unmanaged;
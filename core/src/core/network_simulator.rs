@@ -0,0 +1,110 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use ggrs::{Message, NonBlockingSocket};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::config::{app_config::NetworkSimulationConfig, ggrs_config::Address};
+
+struct DelayedMessage {
+    deliver_at: Instant,
+    address: Address,
+    message: Message,
+}
+
+//Wraps any socket and degrades what it sends, both peers simulating makes the round trip twice the latency
+pub struct NetworkSimulator<S: NonBlockingSocket<Address>> {
+    socket: S,
+    config: Option<NetworkSimulationConfig>,
+    rng: StdRng,
+    outgoing: VecDeque<DelayedMessage>,
+}
+
+impl<S: NonBlockingSocket<Address>> NetworkSimulator<S> {
+    //Without config every message goes straight to the wrapped socket
+    pub fn new(socket: S, config: Option<NetworkSimulationConfig>) -> Self {
+        let seed = config.as_ref().map_or(0, |config| config.seed);
+
+        Self {
+            socket,
+            config,
+            rng: StdRng::seed_from_u64(seed),
+            outgoing: VecDeque::new(),
+        }
+    }
+
+    fn delay(&mut self, config: &NetworkSimulationConfig) -> Duration {
+        let jitter = match config.jitter_ms {
+            0 => 0,
+            jitter => self.rng.gen_range(0..=jitter),
+        };
+
+        //A reordered message is held back long enough to be overtaken by the next ones
+        let reorder = match self.rng.gen_bool(probability(config.reordering)) {
+            true => config.latency_ms.max(1) + config.jitter_ms,
+            false => 0,
+        };
+
+        Duration::from_millis(config.latency_ms + jitter + reorder)
+    }
+
+    fn queue(&mut self, message: &Message, address: &Address, delay: Duration) {
+        let delayed = DelayedMessage {
+            deliver_at: Instant::now() + delay,
+            address: address.clone(),
+            message: message.clone(),
+        };
+
+        let position = self
+            .outgoing
+            .iter()
+            .position(|queued| queued.deliver_at > delayed.deliver_at)
+            .unwrap_or(self.outgoing.len());
+
+        self.outgoing.insert(position, delayed);
+    }
+
+    fn flush(&mut self) {
+        let now = Instant::now();
+
+        while let Some(delayed) = self.outgoing.front() {
+            if delayed.deliver_at > now {
+                break;
+            }
+
+            if let Some(delayed) = self.outgoing.pop_front() {
+                self.socket.send_to(&delayed.message, &delayed.address);
+            }
+        }
+    }
+}
+
+impl<S: NonBlockingSocket<Address>> NonBlockingSocket<Address> for NetworkSimulator<S> {
+    fn send_to(&mut self, msg: &Message, addr: &Address) {
+        let config = match self.config.clone() {
+            Some(config) => config,
+            None => return self.socket.send_to(msg, addr),
+        };
+
+        if !self.rng.gen_bool(probability(config.loss)) {
+            let delay = self.delay(&config);
+            self.queue(msg, addr, delay);
+
+            if self.rng.gen_bool(probability(config.duplication)) {
+                let delay = self.delay(&config);
+                self.queue(msg, addr, delay);
+            }
+        }
+
+        self.flush();
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(Address, Message)> {
+        self.flush();
+        self.socket.receive_all_messages()
+    }
+}
+
+fn probability(value: f64) -> f64 {
+    value.clamp(0.0, 1.0)
+}
@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+
+use chacha20poly1305::{
+    aead::{AeadInPlace, KeyInit},
+    ChaCha20Poly1305, Key, Nonce, Tag,
+};
+use rand::Rng;
+use sha2::{Digest, Sha256};
+
+use crate::config::ggrs_config::Address;
+
+const KEY_CONTEXT: &[u8] = b"ggrs-ffi pre-shared key";
+const MODE_AUTHENTICATED: u8 = 1;
+const MODE_ENCRYPTED: u8 = 2;
+const HEADER_SIZE: usize = 13; //Mode + nonce
+const TAG_SIZE: usize = 16;
+const REPLAY_WINDOW: u64 = 64;
+
+//Nonces are a random salt per socket followed by a counter, the counter also rejects replayed packets
+struct ReplayWindow {
+    salt: [u8; 4],
+    cipher: ChaCha20Poly1305,
+    highest: u64,
+    seen: u64,
+}
+
+impl ReplayWindow {
+    fn new(salt: [u8; 4], cipher: ChaCha20Poly1305, counter: u64) -> Self {
+        Self {
+            salt,
+            cipher,
+            highest: counter,
+            seen: 1,
+        }
+    }
+
+    fn accept(&mut self, counter: u64) -> bool {
+        if counter > self.highest {
+            let shift = counter - self.highest;
+
+            self.seen = match shift {
+                shift if shift >= REPLAY_WINDOW => 1,
+                shift => (self.seen << shift) | 1,
+            };
+            self.highest = counter;
+
+            return true;
+        }
+
+        let age = self.highest - counter;
+
+        if age >= REPLAY_WINDOW || self.seen & (1 << age) != 0 {
+            return false;
+        }
+
+        self.seen |= 1 << age;
+        true
+    }
+}
+
+//Authenticates (and optionally encrypts) every packet with ChaCha20-Poly1305 and a key derived from a passphrase.
+//Each direction has its own key, derived with the salt of the sender, so our own packets reflected back don't open
+pub struct PacketCipher {
+    pre_shared_key: String,
+    cipher: ChaCha20Poly1305,
+    encrypt: bool,
+    salt: [u8; 4],
+    counter: u64,
+    windows: HashMap<Address, ReplayWindow>,
+}
+
+impl PacketCipher {
+    pub fn new(pre_shared_key: &str, encrypt: bool) -> Self {
+        let salt: [u8; 4] = rand::thread_rng().gen();
+
+        Self {
+            pre_shared_key: pre_shared_key.to_string(),
+            cipher: derive_cipher(pre_shared_key, &salt),
+            encrypt,
+            salt,
+            counter: 0,
+            windows: HashMap::new(),
+        }
+    }
+
+    pub fn seal(&mut self, payload: &[u8]) -> Result<Vec<u8>, String> {
+        self.counter += 1;
+
+        let mut packet = Vec::with_capacity(HEADER_SIZE + payload.len() + TAG_SIZE);
+        packet.push(self.mode());
+        packet.extend_from_slice(&self.salt);
+        packet.extend_from_slice(&self.counter.to_le_bytes());
+        packet.extend_from_slice(payload);
+
+        let (header, body) = packet.split_at_mut(HEADER_SIZE);
+        let nonce = Nonce::clone_from_slice(&header[1..]);
+
+        let tag = match self.encrypt {
+            true => self.cipher.encrypt_in_place_detached(&nonce, header, body),
+            false => self
+                .cipher
+                .encrypt_in_place_detached(&nonce, &packet, &mut []),
+        }
+        .map_err(|_| "Couldn't seal packet".to_string())?;
+
+        packet.extend_from_slice(&tag);
+
+        Ok(packet)
+    }
+
+    pub fn open(&mut self, from: &Address, packet: &[u8]) -> Result<Vec<u8>, String> {
+        if packet.len() < HEADER_SIZE + TAG_SIZE {
+            return Err(format!(
+                "{} bytes is too short for a sealed packet",
+                packet.len()
+            ));
+        }
+
+        if packet[0] != self.mode() {
+            return Err("Packet protection mode doesn't match ours".to_string());
+        }
+
+        let (sealed, tag) = packet.split_at(packet.len() - TAG_SIZE);
+        let (header, body) = sealed.split_at(HEADER_SIZE);
+        let nonce = Nonce::clone_from_slice(&header[1..]);
+        let tag = Tag::clone_from_slice(tag);
+        let mut payload = body.to_vec();
+        let salt: [u8; 4] = header[1..5].try_into().unwrap();
+        let counter = u64::from_le_bytes(header[5..].try_into().unwrap());
+
+        if salt == self.salt {
+            return Err("Packet sealed with our own salt, reflected".to_string());
+        }
+
+        //The first salt a peer authenticates with is kept for the whole session, new sessions get a new cipher
+        let window_salt = self.windows.get(from).map(|window| window.salt);
+
+        if window_salt.is_some_and(|window_salt| window_salt != salt) {
+            return Err("Packet salt doesn't match the peer session".to_string());
+        }
+
+        let cipher = match self.windows.get(from) {
+            Some(window) => window.cipher.clone(),
+            None => derive_cipher(&self.pre_shared_key, &salt),
+        };
+
+        match self.encrypt {
+            true => cipher.decrypt_in_place_detached(&nonce, header, &mut payload, &tag),
+            false => cipher.decrypt_in_place_detached(&nonce, sealed, &mut [], &tag),
+        }
+        .map_err(|_| "Packet failed authentication".to_string())?;
+
+        let accepted = match self.windows.get_mut(from) {
+            Some(window) => window.accept(counter),
+            None => {
+                self.windows
+                    .insert(from.clone(), ReplayWindow::new(salt, cipher, counter));
+                true
+            }
+        };
+
+        match accepted {
+            true => Ok(payload),
+            false => Err("Replayed packet".to_string()),
+        }
+    }
+
+    fn mode(&self) -> u8 {
+        match self.encrypt {
+            true => MODE_ENCRYPTED,
+            false => MODE_AUTHENTICATED,
+        }
+    }
+}
+
+//Key of the direction the salt owner sends in
+fn derive_cipher(pre_shared_key: &str, salt: &[u8; 4]) -> ChaCha20Poly1305 {
+    let mut hasher = Sha256::new();
+    hasher.update(KEY_CONTEXT);
+    hasher.update(pre_shared_key.as_bytes());
+    hasher.update(salt);
+    let key = hasher.finalize();
+
+    ChaCha20Poly1305::new(Key::from_slice(&key))
+}
@@ -0,0 +1,295 @@
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{
+    config::{
+        app_config::{ChecksumAlgorithm, DisconnectedInputPolicy, PlayerMetadata},
+        ggrs_config::{Address, ControlKind, PacketGuard},
+    },
+    model::player_info::{ConnectionState, PlayerKind},
+};
+
+const RESEND_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct PlayerEntry {
+    pub handle: Option<usize>, //None for the spectated host and the local spectator
+    pub kind: PlayerKind,
+    pub address: Option<Address>,
+    pub metadata: Option<PlayerMetadata>, //None until the peer sent it
+    pub state: ConnectionState,
+}
+
+//Settings every peer must share to simulate the same frames
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct SessionSettings {
+    pub disconnected_input_policy: DisconnectedInputPolicy,
+    pub checksum: ChecksumAlgorithm, //Checksums of different algorithms never match
+}
+
+impl SessionSettings {
+    //Names the setting that differs, a checksum mismatch would otherwise show up as a desync
+    fn mismatch(&self, address: &Address, theirs: &SessionSettings) -> Option<String> {
+        if self.checksum != theirs.checksum {
+            return Some(format!(
+                "ChecksumAlgorithmMismatch from {} , ours {:?} , theirs {:?}",
+                address, self.checksum, theirs.checksum
+            ));
+        }
+
+        if self.disconnected_input_policy != theirs.disconnected_input_policy {
+            return Some(format!(
+                "DisconnectedInputPolicyMismatch from {} , ours {:?} , theirs {:?}",
+                address, self.disconnected_input_policy, theirs.disconnected_input_policy
+            ));
+        }
+
+        None
+    }
+}
+
+//Sent until the peer says it knows ours, a reply answers every message that isn't one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct MetadataMessage {
+    metadata: PlayerMetadata,
+    settings: SessionSettings,
+    known: bool,
+    reply: bool,
+}
+
+//Who every handle is, filled when the session starts and completed as peers send their metadata
+#[derive(Default)]
+pub struct PlayerRegistry {
+    local: PlayerMetadata,
+    settings: SessionSettings,
+    mismatch: Option<String>,
+    settings_received: HashSet<Address>,
+    entries: Vec<PlayerEntry>,
+    acknowledged: HashSet<Address>,
+    last_sent: Option<Instant>,
+}
+
+impl PlayerRegistry {
+    pub fn new(local: PlayerMetadata, settings: SessionSettings) -> Self {
+        Self {
+            local,
+            settings,
+            ..Default::default()
+        }
+    }
+
+    pub fn add(&mut self, handle: Option<usize>, kind: PlayerKind, address: Option<Address>) {
+        let (metadata, state) = match address {
+            Some(_) => (None, ConnectionState::Connecting),
+            None => (Some(self.local.clone()), ConnectionState::Connected),
+        };
+
+        self.entries.push(PlayerEntry {
+            handle,
+            kind,
+            address,
+            metadata,
+            state,
+        });
+    }
+
+    pub fn entries(&self) -> &[PlayerEntry] {
+        &self.entries
+    }
+
+    //Known ahead when peers met in a lobby
+    pub fn set_metadata(&mut self, address: &Address, metadata: PlayerMetadata) {
+        if let Some(entry) = self.entry_mut(address) {
+            entry.metadata = Some(metadata);
+        }
+    }
+
+    pub fn set_state(&mut self, address: &Address, state: ConnectionState) {
+        if let Some(entry) = self.entry_mut(address) {
+            entry.state = state;
+        }
+    }
+
+    //Reads the metadata peers sent through the session sockets and sends ours to the ones missing it.
+    //Fails once a peer runs with other session settings
+    pub fn exchange(&mut self, guard: &PacketGuard) -> Result<(), String> {
+        if let Some(mismatch) = &self.mismatch {
+            return Err(mismatch.clone());
+        }
+
+        for (address, payload) in guard.take_control(ControlKind::Metadata) {
+            let message: MetadataMessage = match bincode::deserialize(&payload) {
+                Ok(message) => message,
+                Err(e) => {
+                    warn!("Dropped metadata from {} : {}", address, e);
+                    continue;
+                }
+            };
+
+            let entry = match self.entry_mut(&address) {
+                Some(entry) => entry,
+                None => {
+                    warn!("Dropped metadata from {}, not in the session", address);
+                    continue;
+                }
+            };
+
+            if entry.metadata.is_none() {
+                info!("{} is {}", address, message.metadata.display_name);
+            }
+
+            entry.metadata = Some(message.metadata);
+
+            if let Some(mismatch) = self.settings.mismatch(&address, &message.settings) {
+                self.mismatch = Some(mismatch.clone());
+
+                return Err(mismatch);
+            }
+
+            self.settings_received.insert(address.clone());
+
+            if message.known {
+                self.acknowledged.insert(address.clone());
+            }
+
+            if !message.reply {
+                self.send_message(guard, &address, true, true);
+            }
+        }
+
+        if self
+            .last_sent
+            .is_some_and(|last| last.elapsed() < RESEND_INTERVAL)
+        {
+            return Ok(());
+        }
+
+        let pending: Vec<(Address, bool)> = self
+            .entries
+            .iter()
+            .filter_map(|entry| match &entry.address {
+                Some(address) if !self.acknowledged.contains(address) => {
+                    Some((address.clone(), entry.metadata.is_some()))
+                }
+                _ => None,
+            })
+            .collect();
+
+        for (address, known) in pending {
+            self.send_message(guard, &address, known, false);
+        }
+
+        self.last_sent = Some(Instant::now());
+
+        Ok(())
+    }
+
+    //Settings come through unreliable packets, a remote player can't be trusted until its own arrived
+    pub fn settings_agreed(&self) -> bool {
+        self.mismatch.is_none()
+            && self
+                .entries
+                .iter()
+                .filter(|entry| entry.kind == PlayerKind::Remote)
+                .filter_map(|entry| entry.address.as_ref())
+                .all(|address| self.settings_received.contains(address))
+    }
+
+    fn send_message(&self, guard: &PacketGuard, address: &Address, known: bool, reply: bool) {
+        let message = MetadataMessage {
+            metadata: self.local.clone(),
+            settings: self.settings,
+            known,
+            reply,
+        };
+
+        match bincode::serialize(&message) {
+            Ok(payload) => guard.post_control(address, ControlKind::Metadata, &payload),
+            Err(e) => warn!("Couldn't serialize metadata : {}", e),
+        }
+    }
+
+    fn entry_mut(&mut self, address: &Address) -> Option<&mut PlayerEntry> {
+        self.entries
+            .iter_mut()
+            .find(|entry| entry.address.as_ref() == Some(address))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ggrs_config::Transport;
+
+    fn peer(id: u32, remote: u32, settings: SessionSettings) -> (PlayerRegistry, PacketGuard) {
+        let mut registry = PlayerRegistry::new(PlayerMetadata::default(), settings);
+
+        registry.add(Some(id as usize), PlayerKind::Local, None);
+        registry.add(
+            Some(remote as usize),
+            PlayerKind::Remote,
+            Some(Address::Loopback(remote)),
+        );
+
+        (registry, PacketGuard::default())
+    }
+
+    fn deliver(from: &PacketGuard, from_id: u32, to: &PacketGuard) {
+        for (_, packet) in from.take_outgoing_control(Transport::Loopback) {
+            to.decode(&Address::Loopback(from_id), &packet);
+        }
+    }
+
+    #[test]
+    fn dropped_settings_keep_the_peers_waiting() {
+        let (mut a, guard_a) = peer(0, 1, SessionSettings::default());
+        let (mut b, guard_b) = peer(1, 0, SessionSettings::default());
+
+        a.exchange(&guard_a).unwrap();
+        guard_a.take_outgoing_control(Transport::Loopback);
+        b.exchange(&guard_b).unwrap();
+
+        assert!(!a.settings_agreed());
+        assert!(!b.settings_agreed());
+
+        deliver(&guard_b, 1, &guard_a);
+        a.exchange(&guard_a).unwrap();
+
+        assert!(a.settings_agreed());
+        assert!(!b.settings_agreed());
+
+        deliver(&guard_a, 0, &guard_b);
+        b.exchange(&guard_b).unwrap();
+
+        assert!(b.settings_agreed());
+    }
+
+    #[test]
+    fn delayed_settings_mismatch_is_found_before_agreeing() {
+        let (mut a, guard_a) = peer(0, 1, SessionSettings::default());
+        let (mut b, guard_b) = peer(
+            1,
+            0,
+            SessionSettings {
+                disconnected_input_policy: DisconnectedInputPolicy::RepeatLastConfirmed,
+                ..Default::default()
+            },
+        );
+
+        a.exchange(&guard_a).unwrap();
+        b.exchange(&guard_b).unwrap();
+
+        assert!(!a.settings_agreed());
+
+        deliver(&guard_b, 1, &guard_a);
+
+        assert!(a
+            .exchange(&guard_a)
+            .is_err_and(|e| e.starts_with("DisconnectedInputPolicyMismatch")));
+        assert!(!a.settings_agreed());
+        assert!(a.exchange(&guard_a).is_err());
+    }
+}
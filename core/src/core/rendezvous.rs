@@ -0,0 +1,254 @@
+use std::net::SocketAddr;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::config::ggrs_config::DirectUdpSocket;
+
+const MAGIC: &[u8; 8] = b"GGRSRDV1";
+const REGISTER_INTERVAL: Duration = Duration::from_millis(500);
+const PUNCH_INTERVAL: Duration = Duration::from_millis(100);
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum RendezvousMessage {
+    Register {
+        session: String,
+    },
+    Registered,
+    Peer {
+        endpoint: SocketAddr,
+        player_draw: u32,
+    },
+    Punch {
+        session: String,
+    },
+    PunchAck {
+        session: String,
+    },
+    Join,
+    JoinAck,
+}
+
+pub fn encode(message: &RendezvousMessage) -> Vec<u8> {
+    let mut packet = MAGIC.to_vec();
+    packet.extend(bincode::serialize(message).unwrap_or_default());
+    packet
+}
+
+pub fn decode(packet: &[u8]) -> Option<RendezvousMessage> {
+    match is_handshake(packet) {
+        true => bincode::deserialize(&packet[MAGIC.len()..]).ok(),
+        false => None,
+    }
+}
+
+pub fn is_handshake(packet: &[u8]) -> bool {
+    packet.starts_with(MAGIC)
+}
+
+//Both peers of a pair get the same draws whatever the message order, the lower endpoint is 0
+pub fn player_draw(endpoint: &SocketAddr, peer: &SocketAddr) -> u32 {
+    (endpoint > peer) as u32
+}
+
+//Registers with the rendezvous server then punches toward the peer it reports, returns the peer
+//endpoint and the player draw the server assigned
+pub fn connect(
+    socket: &mut DirectUdpSocket,
+    server: &str,
+    session: &str,
+    timeout: Duration,
+) -> Result<(SocketAddr, u32), String> {
+    let server = socket.resolve(server)?;
+    let start = Instant::now();
+    let mut peer: Option<(SocketAddr, u32)> = None;
+    let mut last_register: Option<Instant> = None;
+    let mut last_punch: Option<Instant> = None;
+
+    info!(
+        "Registering to rendezvous server {} for {}",
+        server, session
+    );
+
+    while start.elapsed() < timeout {
+        for (from, packet) in socket.receive_raw() {
+            //The server doesn't know the pre shared key, peers must prove they do
+            if from == server {
+                if let Some(RendezvousMessage::Peer {
+                    endpoint,
+                    player_draw,
+                }) = decode(&packet)
+                {
+                    if peer.map(|(known, _)| known) != Some(endpoint) {
+                        info!(
+                            "Rendezvous server reported peer at {}, player draw {}",
+                            endpoint, player_draw
+                        );
+                    }
+
+                    peer = Some((endpoint, player_draw));
+                }
+
+                continue;
+            }
+
+            let packet = match socket.open_handshake(&from, packet) {
+                Some(packet) => packet,
+                None => continue,
+            };
+
+            match (decode(&packet), peer) {
+                (Some(RendezvousMessage::Punch { session: punched }), Some((_, player_draw)))
+                | (
+                    Some(RendezvousMessage::PunchAck { session: punched }),
+                    Some((_, player_draw)),
+                ) if punched == session => {
+                    //The NAT may have mapped the peer to another port than the one the server saw
+                    return punched_through(socket, from, session, player_draw);
+                }
+                //The peer already started its session, its GGRS packets prove the path works too
+                (None, Some((endpoint, player_draw))) if from == endpoint => {
+                    return punched_through(socket, from, session, player_draw);
+                }
+                _ => {}
+            }
+        }
+
+        match peer {
+            Some((peer, _)) => {
+                if last_punch.is_none_or(|last| last.elapsed() >= PUNCH_INTERVAL) {
+                    send_to_peer(
+                        socket,
+                        &RendezvousMessage::Punch {
+                            session: session.to_string(),
+                        },
+                        &peer,
+                    );
+                    last_punch = Some(Instant::now());
+                }
+            }
+            None => {
+                if last_register.is_none_or(|last| last.elapsed() >= REGISTER_INTERVAL) {
+                    send(
+                        socket,
+                        &RendezvousMessage::Register {
+                            session: session.to_string(),
+                        },
+                        &server,
+                    );
+                    last_register = Some(Instant::now());
+                }
+            }
+        }
+
+        sleep(POLL_INTERVAL);
+    }
+
+    Err(match peer {
+        Some((peer, _)) => format!("Couldn't punch through to {} for {}", peer, session),
+        None => format!("No peer registered for {} on {}", session, server),
+    })
+}
+
+fn punched_through(
+    socket: &mut DirectUdpSocket,
+    peer: SocketAddr,
+    session: &str,
+    player_draw: u32,
+) -> Result<(SocketAddr, u32), String> {
+    info!("Punched through to {}", peer);
+
+    //Let the peer know too, in case none of our punches reached it yet
+    send_to_peer(
+        socket,
+        &RendezvousMessage::PunchAck {
+            session: session.to_string(),
+        },
+        &peer,
+    );
+
+    Ok((peer, player_draw))
+}
+
+//Host side of a LAN join, returns the endpoint of the first peer asking to join
+pub fn wait_for_join(
+    socket: &mut DirectUdpSocket,
+    timeout: Duration,
+) -> Result<SocketAddr, String> {
+    let start = Instant::now();
+
+    while start.elapsed() < timeout {
+        for (from, packet) in socket.receive_raw() {
+            let packet = match socket.open_handshake(&from, packet) {
+                Some(packet) => packet,
+                None => continue,
+            };
+
+            if decode(&packet) == Some(RendezvousMessage::Join) {
+                info!("{} joined", from);
+
+                send_to_peer(socket, &RendezvousMessage::JoinAck, &from);
+
+                return Ok(from);
+            }
+        }
+
+        sleep(POLL_INTERVAL);
+    }
+
+    Err(format!("Nobody joined within {}s", timeout.as_secs()))
+}
+
+//Client side of a LAN join, returns once the host acknowledged it
+pub fn join(
+    socket: &mut DirectUdpSocket,
+    host: SocketAddr,
+    timeout: Duration,
+) -> Result<(), String> {
+    let start = Instant::now();
+    let mut last_join: Option<Instant> = None;
+
+    info!("Joining {}", host);
+
+    while start.elapsed() < timeout {
+        for (from, packet) in socket.receive_raw() {
+            if from != host {
+                continue;
+            }
+
+            let packet = match socket.open_handshake(&from, packet) {
+                Some(packet) => packet,
+                None => continue,
+            };
+
+            match decode(&packet) {
+                Some(RendezvousMessage::JoinAck) => return Ok(()),
+                //The acknowledgment got lost but the host session already talks to us
+                None => return Ok(()),
+                _ => {}
+            }
+        }
+
+        if last_join.is_none_or(|last| last.elapsed() >= PUNCH_INTERVAL) {
+            send_to_peer(socket, &RendezvousMessage::Join, &host);
+            last_join = Some(Instant::now());
+        }
+
+        sleep(POLL_INTERVAL);
+    }
+
+    Err(format!("{} didn't answer the join request", host))
+}
+
+pub fn send(socket: &DirectUdpSocket, message: &RendezvousMessage, addr: &SocketAddr) {
+    if let Err(e) = socket.send_raw(&encode(message), addr) {
+        warn!("Couldn't send rendezvous message to {} : {}", addr, e);
+    }
+}
+
+fn send_to_peer(socket: &mut DirectUdpSocket, message: &RendezvousMessage, addr: &SocketAddr) {
+    socket.send_handshake(encode(message), addr);
+}
@@ -0,0 +1,349 @@
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::time::Instant;
+
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+
+use crate::{
+    config::app_config::{StateCompression, StateStorageConfig},
+    model::{
+        game_state::{GameState, StateEncoding},
+        state_stats::StateStats,
+    },
+};
+
+#[derive(Debug, Default)]
+pub struct StateStore {
+    compression: StateCompression,
+    keyframe_interval: Option<i32>,
+    max_prediction: i32,
+    keyframes: BTreeMap<i32, Vec<u8>>,
+    saved_states: u32,
+    loaded_states: u32,
+    raw_bytes: u64,
+    stored_bytes: u64,
+    encode_micros: u128,
+    decode_micros: u128,
+}
+
+impl StateStore {
+    pub fn new(config: &StateStorageConfig, max_prediction: usize) -> Self {
+        Self {
+            compression: match config.compression {
+                StateCompression::Deflate(level) => StateCompression::Deflate(level.min(9)),
+                compression => compression,
+            },
+            keyframe_interval: config.keyframe_interval.map(|i| i.max(1) as i32),
+            max_prediction: max_prediction as i32,
+            ..Default::default()
+        }
+    }
+
+    //Encode a raw game state the way it will be kept by GGRS
+    pub fn store(&mut self, game_state: &GameState, frame: i32) -> Result<GameState, String> {
+        let start = Instant::now();
+        let raw_size = game_state.bytes().len();
+        let sections = game_state.sections().to_vec();
+
+        let mut stored = match self.keyframe_interval {
+            Some(interval) => self.store_delta(game_state, frame, interval)?,
+            None => self.compress(game_state.bytes())?,
+        };
+
+        stored.set_sections(sections);
+
+        let kept_bytes = match stored.encoding() {
+            StateEncoding::Keyframe { frame: _ } => raw_size,
+            _ => stored.bytes().len(),
+        };
+
+        self.saved_states += 1;
+        self.raw_bytes += raw_size as u64;
+        self.stored_bytes += kept_bytes as u64;
+        self.encode_micros += start.elapsed().as_micros();
+
+        Ok(stored)
+    }
+
+    //Decode a game state loaded from GGRS back to the bytes the host saved
+    pub fn restore(&mut self, game_state: &GameState) -> Result<GameState, String> {
+        let start = Instant::now();
+
+        let mut restored = match game_state.encoding() {
+            StateEncoding::Raw => game_state.clone(),
+            StateEncoding::Deflate { raw_size } => {
+                GameState::encoded(inflate(game_state.bytes(), raw_size)?, StateEncoding::Raw)
+            }
+            StateEncoding::Keyframe { frame } => match self.keyframes.get(&frame) {
+                Some(keyframe) => GameState::encoded(keyframe.clone(), StateEncoding::Raw),
+                None => return Err(format!("Keyframe {} not found", frame)),
+            },
+            StateEncoding::Delta {
+                keyframe,
+                raw_size,
+                deflated,
+            } => {
+                let base = match self.keyframes.get(&keyframe) {
+                    Some(base) => base,
+                    None => return Err(format!("Keyframe {} not found", keyframe)),
+                };
+
+                let raw = match deflated {
+                    true => {
+                        let delta = inflate_unsized(game_state.bytes())?;
+                        xor_rle_decode(base, &delta, raw_size)?
+                    }
+                    false => xor_rle_decode(base, game_state.bytes(), raw_size)?,
+                };
+
+                GameState::encoded(raw, StateEncoding::Raw)
+            }
+        };
+
+        restored.set_sections(game_state.sections().to_vec());
+
+        self.loaded_states += 1;
+        self.decode_micros += start.elapsed().as_micros();
+
+        Ok(restored)
+    }
+
+    pub fn stats(&self) -> StateStats {
+        let ratio = match self.stored_bytes {
+            0 => 1.0,
+            stored => self.raw_bytes as f32 / stored as f32,
+        };
+
+        StateStats {
+            saved_states: self.saved_states,
+            loaded_states: self.loaded_states,
+            raw_bytes: self.raw_bytes,
+            stored_bytes: self.stored_bytes,
+            compression_ratio: ratio,
+            avg_encode_micros: average(self.encode_micros, self.saved_states),
+            avg_decode_micros: average(self.decode_micros, self.loaded_states),
+        }
+    }
+
+    fn compress(&self, raw: &[u8]) -> Result<GameState, String> {
+        match self.compression {
+            StateCompression::None => Ok(GameState::encoded(raw.to_vec(), StateEncoding::Raw)),
+            StateCompression::Deflate(level) => Ok(GameState::encoded(
+                deflate(raw, level)?,
+                StateEncoding::Deflate {
+                    raw_size: raw.len(),
+                },
+            )),
+        }
+    }
+
+    fn store_delta(
+        &mut self,
+        game_state: &GameState,
+        frame: i32,
+        interval: i32,
+    ) -> Result<GameState, String> {
+        //Saving a frame again means a rollback happened, later keyframes are stale
+        self.keyframes.retain(|keyframe, _| *keyframe < frame);
+
+        let base = self
+            .keyframes
+            .range(..frame)
+            .next_back()
+            .map(|(keyframe, _)| *keyframe)
+            .filter(|keyframe| frame - keyframe < interval);
+
+        let stored = match base {
+            Some(keyframe) => {
+                let raw_size = game_state.bytes().len();
+                let delta = xor_rle_encode(&self.keyframes[&keyframe], game_state.bytes());
+
+                match self.compression {
+                    StateCompression::None => GameState::encoded(
+                        delta,
+                        StateEncoding::Delta {
+                            keyframe,
+                            raw_size,
+                            deflated: false,
+                        },
+                    ),
+                    StateCompression::Deflate(level) => GameState::encoded(
+                        deflate(&delta, level)?,
+                        StateEncoding::Delta {
+                            keyframe,
+                            raw_size,
+                            deflated: true,
+                        },
+                    ),
+                }
+            }
+            None => {
+                self.keyframes.insert(frame, game_state.bytes().to_vec());
+
+                GameState::encoded(vec![], StateEncoding::Keyframe { frame })
+            }
+        };
+
+        self.prune_keyframes(frame);
+
+        Ok(stored)
+    }
+
+    //Only the newest keyframe older than the rollback window can still be referenced
+    fn prune_keyframes(&mut self, frame: i32) {
+        let horizon = frame - self.max_prediction - 2;
+
+        let oldest_needed = self
+            .keyframes
+            .range(..=horizon)
+            .next_back()
+            .map(|(keyframe, _)| *keyframe);
+
+        if let Some(oldest_needed) = oldest_needed {
+            self.keyframes = self.keyframes.split_off(&oldest_needed);
+        }
+    }
+}
+
+fn average(total_micros: u128, count: u32) -> f32 {
+    match count {
+        0 => 0.0,
+        count => total_micros as f32 / count as f32,
+    }
+}
+
+fn deflate(raw: &[u8], level: u32) -> Result<Vec<u8>, String> {
+    let mut encoder =
+        DeflateEncoder::new(Vec::with_capacity(raw.len() / 2), Compression::new(level));
+
+    encoder
+        .write_all(raw)
+        .map_err(|e| format!("Couldn't compress game state : {}", e))?;
+
+    encoder
+        .finish()
+        .map_err(|e| format!("Couldn't compress game state : {}", e))
+}
+
+fn inflate(compressed: &[u8], raw_size: usize) -> Result<Vec<u8>, String> {
+    let raw = inflate_unsized(compressed)?;
+
+    if raw.len() != raw_size {
+        return Err(format!(
+            "Decompressed game state size mismatch, expected {} got {}",
+            raw_size,
+            raw.len()
+        ));
+    }
+
+    Ok(raw)
+}
+
+fn inflate_unsized(compressed: &[u8]) -> Result<Vec<u8>, String> {
+    let mut raw = Vec::with_capacity(compressed.len() * 2);
+
+    DeflateDecoder::new(compressed)
+        .read_to_end(&mut raw)
+        .map_err(|e| format!("Couldn't decompress game state : {}", e))?;
+
+    Ok(raw)
+}
+
+fn base_byte(base: &[u8], index: usize) -> u8 {
+    base.get(index).copied().unwrap_or(0)
+}
+
+//The delta is a list of (unchanged run length, changed run length, changed bytes XOR base)
+fn xor_rle_encode(base: &[u8], raw: &[u8]) -> Vec<u8> {
+    let mut delta = vec![];
+    let mut index = 0;
+
+    while index < raw.len() {
+        let unchanged_start = index;
+        while index < raw.len() && raw[index] == base_byte(base, index) {
+            index += 1;
+        }
+
+        let changed_start = index;
+        while index < raw.len() && raw[index] != base_byte(base, index) {
+            index += 1;
+        }
+
+        write_varint(&mut delta, changed_start - unchanged_start);
+        write_varint(&mut delta, index - changed_start);
+        delta.extend(
+            (changed_start..index).map(|position| raw[position] ^ base_byte(base, position)),
+        );
+    }
+
+    delta
+}
+
+fn xor_rle_decode(base: &[u8], delta: &[u8], raw_size: usize) -> Result<Vec<u8>, String> {
+    let mut raw = Vec::with_capacity(raw_size);
+    let mut cursor = 0;
+
+    while cursor < delta.len() {
+        let unchanged = read_varint(delta, &mut cursor)?;
+        let changed = read_varint(delta, &mut cursor)?;
+
+        if raw.len() + unchanged + changed > raw_size || cursor + changed > delta.len() {
+            return Err("Corrupted game state delta".to_string());
+        }
+
+        for _ in 0..unchanged {
+            raw.push(base_byte(base, raw.len()));
+        }
+
+        for byte in &delta[cursor..cursor + changed] {
+            raw.push(byte ^ base_byte(base, raw.len()));
+        }
+
+        cursor += changed;
+    }
+
+    if raw.len() != raw_size {
+        return Err(format!(
+            "Game state delta size mismatch, expected {} got {}",
+            raw_size,
+            raw.len()
+        ));
+    }
+
+    Ok(raw)
+}
+
+fn write_varint(buffer: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        buffer.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+
+    buffer.push(value as u8);
+}
+
+fn read_varint(buffer: &[u8], cursor: &mut usize) -> Result<usize, String> {
+    let mut value: usize = 0;
+    let mut shift = 0;
+
+    loop {
+        let byte = match buffer.get(*cursor) {
+            Some(byte) => *byte,
+            None => return Err("Corrupted game state delta".to_string()),
+        };
+
+        *cursor += 1;
+
+        if shift >= usize::BITS {
+            return Err("Corrupted game state delta".to_string());
+        }
+
+        value |= ((byte & 0x7f) as usize) << shift;
+
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+
+        shift += 7;
+    }
+}
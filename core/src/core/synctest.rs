@@ -0,0 +1,134 @@
+use std::collections::BTreeMap;
+
+use crate::model::{
+    game_state::GameState,
+    state_section::{diverged_sections, StateSection},
+};
+
+const KEPT_MISMATCHES: usize = 16;
+
+struct SyncTestSave {
+    checksum: u128,
+    sections: Vec<StateSection>,
+    bytes: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SyncTestMismatch {
+    pub frame: i32,
+    pub expected_checksum: u128,
+    pub actual_checksum: u128,
+    pub checksums_known: bool, //False when GGRS failed a frame the tracker saw no mismatch for
+    pub diverged_sections: Vec<String>,
+    pub expected_state: Option<Vec<u8>>,
+    pub actual_state: Option<Vec<u8>>,
+}
+
+//SyncTest saves a frame again after each rollback, the first save is the reference
+pub struct SyncTestTracker {
+    capture_states: bool,
+    depth: i32,
+    saves: BTreeMap<i32, SyncTestSave>,
+    mismatches: BTreeMap<i32, SyncTestMismatch>,
+    failed_frame: Option<i32>,
+}
+
+impl SyncTestTracker {
+    pub fn new(check_distance: usize, capture_states: bool) -> Self {
+        Self {
+            capture_states,
+            depth: check_distance as i32 + 2,
+            saves: BTreeMap::new(),
+            mismatches: BTreeMap::new(),
+            failed_frame: None,
+        }
+    }
+
+    pub fn record(&mut self, frame: i32, checksum: u128, game_state: &GameState) {
+        match self.saves.get(&frame) {
+            Some(first) if first.checksum != checksum => {
+                if !self.mismatches.contains_key(&frame) {
+                    self.mismatches.insert(
+                        frame,
+                        SyncTestMismatch {
+                            frame,
+                            expected_checksum: first.checksum,
+                            actual_checksum: checksum,
+                            checksums_known: true,
+                            diverged_sections: diverged_sections(
+                                &first.sections,
+                                game_state.sections(),
+                            ),
+                            expected_state: first.bytes.clone(),
+                            actual_state: match self.capture_states {
+                                true => Some(game_state.bytes().to_vec()),
+                                false => None,
+                            },
+                        },
+                    );
+                }
+            }
+            Some(_) => {}
+            None => {
+                self.saves.insert(
+                    frame,
+                    SyncTestSave {
+                        checksum,
+                        sections: game_state.sections().to_vec(),
+                        bytes: match self.capture_states {
+                            true => Some(game_state.bytes().to_vec()),
+                            false => None,
+                        },
+                    },
+                );
+            }
+        }
+
+        let horizon = frame - self.depth;
+        self.saves.retain(|saved, _| *saved >= horizon);
+
+        while self.mismatches.len() > KEPT_MISMATCHES {
+            self.mismatches.pop_first();
+        }
+    }
+
+    pub fn fail(&mut self, frame: i32) {
+        self.failed_frame = Some(frame);
+    }
+
+    //The frame GGRS failed on if any, the latest recorded mismatch otherwise
+    pub fn report(&self) -> Option<SyncTestMismatch> {
+        match self.failed_frame {
+            Some(frame) => match self.mismatches.get(&frame) {
+                Some(mismatch) => Some(mismatch.clone()),
+                None => Some(SyncTestMismatch {
+                    frame,
+                    expected_checksum: 0,
+                    actual_checksum: 0,
+                    checksums_known: false,
+                    diverged_sections: vec![],
+                    expected_state: None,
+                    actual_state: None,
+                }),
+            },
+            None => self.mismatches.values().next_back().cloned(),
+        }
+    }
+
+    pub fn mismatch_message(&self, frame: i32) -> String {
+        match self.mismatches.get(&frame) {
+            Some(mismatch) if !mismatch.diverged_sections.is_empty() => format!(
+                "GGRSError : Checksum mismatch at frame {} , expected {:032x} got {:032x} , diverged sections : {}",
+                frame,
+                mismatch.expected_checksum,
+                mismatch.actual_checksum,
+                mismatch.diverged_sections.join(", ")
+            ),
+            Some(mismatch) => format!(
+                "GGRSError : Checksum mismatch at frame {} , expected {:032x} got {:032x}",
+                frame, mismatch.expected_checksum, mismatch.actual_checksum
+            ),
+            None => format!("GGRSError : Checksum mismatch at frame {}", frame),
+        }
+    }
+}
@@ -12,6 +12,28 @@ impl SafeBytes {
         Self { ptr, size }
     }
 
+    pub fn from_vec(bytes: Vec<u8>) -> Self {
+        let boxed = bytes.into_boxed_slice();
+        let size = boxed.len();
+
+        Self::new(Box::into_raw(boxed) as *mut u8, size)
+    }
+
+    pub fn empty() -> Self {
+        Self::new(std::ptr::null_mut(), 0)
+    }
+
+    //Only for bytes created with from_vec
+    pub unsafe fn free(self) {
+        if self.ptr.is_null() {
+            return;
+        }
+
+        drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+            self.ptr, self.size,
+        )));
+    }
+
     pub unsafe fn slice(&self) -> &[u8] {
         slice::from_raw_parts(self.ptr, self.size)
     }
@@ -1,5 +1,3 @@
-use std::mem::forget;
-
 use serde::{Deserialize, Serialize};
 
 use super::safe_bytes::SafeBytes;
@@ -19,6 +17,16 @@ impl UnmanagedBytes {
         }
     }
 
+    pub fn from_vec(bytes: Vec<u8>) -> Self {
+        let size = bytes.len();
+
+        Self { bytes, size }
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.bytes
+    }
+
     pub fn bytes(&self) -> Vec<u8> {
         self.bytes.clone()
     }
@@ -34,13 +42,8 @@ impl UnmanagedBytes {
         }
     }
 
-    pub fn to_safe_bytes(&mut self) -> SafeBytes {
-        let mut data = self.bytes.clone();
-
-        let safe = Box::new(SafeBytes::new(data.as_mut_ptr(), self.size));
-
-        forget(data);
-
-        *safe
+    //Freed with SafeBytes::free
+    pub fn to_safe_bytes(&self) -> SafeBytes {
+        SafeBytes::from_vec(self.bytes.clone())
     }
 }
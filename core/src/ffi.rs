@@ -6,19 +6,31 @@ use crate::{
     config::app_config::AppConfig,
     core::{
         action_result::ActionResult,
+        checksum::ChecksumSource,
+        lobby::LobbyRole,
         unmanaged::{safe_bytes::SafeBytes, unmanaged_bytes::UnmanagedBytes},
     },
     get_netplay_intance, has_netplay_disconnected,
     model::{
-        ffi::{input_ffi::Inputs, netplay_request_ffi::NetplayRequests},
+        byte_range::ByteRange,
+        ffi::{
+            discovered_host_ffi::DiscoveredHosts,
+            game_state_section_ffi::{GameStateSection, GameStateSectionLayouts},
+            input_ffi::Inputs,
+            lobby_peer_ffi::LobbyPeers,
+            netplay_request_ffi::NetplayRequests,
+            player_info_ffi::PlayerInfos,
+        },
         game_state::GameState,
-        input::Input,
+        input::{DisconnectedInputCallback, Input},
         netplay_request::NetplayRequest,
         network_stats::NetworkStats,
+        state_stats::StateStats,
+        synctest_report::SyncTestReport,
     },
     Events, Status,
 };
-use std::ffi::CString;
+use std::ffi::{CStr, CString};
 
 #[no_mangle]
 #[catch_status]
@@ -30,6 +42,18 @@ pub unsafe extern "C" fn netplay_init(config: SafeBytes) -> Status {
     np.init(safe_config)
 }
 
+#[no_mangle]
+#[catch_status]
+pub unsafe extern "C" fn netplay_set_disconnected_input_callback(
+    callback: Option<DisconnectedInputCallback>,
+) -> Status {
+    let mut np = get_netplay_intance().lock().unwrap();
+
+    np.set_disconnected_input_callback(callback);
+
+    Ok::<(), String>(())
+}
+
 #[no_mangle]
 #[catch_status]
 pub extern "C" fn netplay_poll() -> Status {
@@ -64,7 +88,7 @@ pub extern "C" fn netplay_is_disconnected() -> Status {
 pub unsafe extern "C" fn netplay_events() -> Events {
     let mut np = get_netplay_intance().lock().unwrap();
 
-    return Events::new(np.events());
+    Events::new(np.events())
 }
 
 #[no_mangle]
@@ -93,7 +117,7 @@ pub unsafe extern "C" fn netplay_advance_frame(input: Input) -> Status {
     });
 
     match res {
-        Ok(status) => return status,
+        Ok(status) => status,
         Err(e) => {
             let error_msg = if let Some(s) = e.downcast_ref::<&str>() {
                 s.to_string()
@@ -102,11 +126,28 @@ pub unsafe extern "C" fn netplay_advance_frame(input: Input) -> Status {
             } else {
                 "unknown error".to_string()
             };
-            return Status::ko(Box::leak(error_msg.into_boxed_str()));
+            Status::ko(Box::leak(error_msg.into_boxed_str()))
         }
     }
 }
 
+//Test sessions only, one input per host handle in the order of the test config
+#[no_mangle]
+#[catch_status]
+pub unsafe extern "C" fn netplay_advance_frame_with_inputs(
+    inputs: *const Input,
+    inputs_len: usize,
+) -> Status {
+    let mut np = get_netplay_intance().lock().unwrap();
+
+    let inputs = match inputs.is_null() {
+        true => vec![],
+        false => std::slice::from_raw_parts(inputs, inputs_len).to_vec(),
+    };
+
+    np.advance_frame_with_inputs(inputs)
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn netplay_get_requests() -> NetplayRequests {
     let np = get_netplay_intance().lock().unwrap();
@@ -116,7 +157,7 @@ pub unsafe extern "C" fn netplay_get_requests() -> NetplayRequests {
 
     forget(requests);
 
-    return reqs;
+    reqs
 }
 
 #[no_mangle]
@@ -138,7 +179,79 @@ pub unsafe extern "C" fn netplay_save_game_state(game_state: SafeBytes) -> Statu
 
     let safe_game_state = GameState::new(game_state);
 
-    np.handle_save_game_state_request(safe_game_state)
+    np.handle_save_game_state_request(safe_game_state, ChecksumSource::Full)
+}
+
+#[no_mangle]
+#[catch_status]
+pub unsafe extern "C" fn netplay_save_game_state_with_checksum(
+    game_state: SafeBytes,
+    checksum_low: u64,
+    checksum_high: u64,
+) -> Status {
+    let mut np = get_netplay_intance().lock().unwrap();
+
+    let safe_game_state = GameState::new(game_state);
+    let checksum = ((checksum_high as u128) << 64) | checksum_low as u128;
+
+    np.handle_save_game_state_request(safe_game_state, ChecksumSource::Host(checksum))
+}
+
+#[no_mangle]
+#[catch_status]
+pub unsafe extern "C" fn netplay_save_game_state_with_ranges(
+    game_state: SafeBytes,
+    ranges: *const ByteRange,
+    ranges_len: usize,
+) -> Status {
+    let mut np = get_netplay_intance().lock().unwrap();
+
+    let safe_game_state = GameState::new(game_state);
+    let ranges = match ranges.is_null() || ranges_len == 0 {
+        true => vec![],
+        false => std::slice::from_raw_parts(ranges, ranges_len).to_vec(),
+    };
+
+    np.handle_save_game_state_request(safe_game_state, ChecksumSource::Ranges(ranges))
+}
+
+#[no_mangle]
+#[catch_status]
+pub unsafe extern "C" fn netplay_save_game_state_sections(
+    sections: *const GameStateSection,
+    sections_len: usize,
+) -> Status {
+    let mut np = get_netplay_intance().lock().unwrap();
+
+    let sections = match sections.is_null() {
+        true => vec![],
+        false => std::slice::from_raw_parts(sections, sections_len)
+            .iter()
+            .map(|section| section.read())
+            .collect(),
+    };
+
+    np.handle_save_game_state_sections_request(sections)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn netplay_game_state_sections() -> GameStateSectionLayouts {
+    let np = get_netplay_intance().lock().unwrap();
+
+    GameStateSectionLayouts::new(&np.game_state_sections())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn netplay_game_state_sections_free(layouts: GameStateSectionLayouts) {
+    if layouts.data.is_null() {
+        return;
+    }
+
+    let layouts = Vec::from_raw_parts(layouts.data, layouts.len, layouts.len);
+
+    for layout in layouts {
+        status_info_free(layout.name);
+    }
 }
 
 #[no_mangle]
@@ -149,7 +262,7 @@ pub unsafe extern "C" fn netplay_advance_game_state() -> Inputs {
     let inputs_ffi = Inputs::new(inputs.clone());
     forget(inputs);
 
-    return inputs_ffi;
+    inputs_ffi
 }
 
 #[no_mangle]
@@ -179,21 +292,50 @@ pub unsafe extern "C" fn netplay_network_stats(network_stats: *mut NetworkStats)
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn netplay_frames_ahead() -> i32 {
-    let mut np = get_netplay_intance().lock().unwrap();
+pub unsafe extern "C" fn netplay_state_stats(state_stats: *mut StateStats) -> Status {
+    let np = get_netplay_intance().lock().unwrap();
+
+    match np.state_stats(state_stats) {
+        Ok(_) => Status::ok(),
+        Err(e) => Status::ko(Box::leak(e.into_boxed_str())),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn netplay_synctest_report(report: *mut SyncTestReport) -> Status {
+    let np = get_netplay_intance().lock().unwrap();
+
+    if report.is_null() {
+        return Status::ko("synctest_report : null pointer");
+    }
 
-    match np.frames_ahead() {
-        Ok(frames_ahead) => frames_ahead,
-        Err(_) => -1,
+    match np.synctest_report() {
+        Some(mismatch) => {
+            report.write(SyncTestReport::new(mismatch));
+            Status::ok()
+        }
+        None => Status::ko("No synctest mismatch"),
     }
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn netplay_free_game_state(safe_bytes: SafeBytes) {
+pub unsafe extern "C" fn netplay_synctest_report_free(report: SyncTestReport) {
+    report.expected_state.free();
+    report.actual_state.free();
+    status_info_free(report.diverged_sections);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn netplay_frames_ahead() -> i32 {
     let mut np = get_netplay_intance().lock().unwrap();
 
-    let slice = safe_bytes.slice();
-    drop(slice);
+    np.frames_ahead().unwrap_or(-1)
+}
+
+#[no_mangle]
+//The host owns the bytes, only the game state kept here is released
+pub unsafe extern "C" fn netplay_free_game_state(_safe_bytes: SafeBytes) {
+    let mut np = get_netplay_intance().lock().unwrap();
 
     np.reset_game_state();
 }
@@ -226,3 +368,186 @@ pub unsafe extern "C" fn netplay_remote_player_handle() -> i32 {
 
     np.remote_player_handle()
 }
+
+//Port 0 lets the system pick one, read it back with netplay_local_port. Used by LocalConf sessions and UDP spectators
+#[no_mangle]
+#[catch_status]
+pub unsafe extern "C" fn netplay_bind_udp(bind_addr: *const c_char, port: u16) -> Status {
+    let mut np = get_netplay_intance().lock().unwrap();
+
+    let bind_addr = match bind_addr.is_null() {
+        true => None,
+        false => Some(CStr::from_ptr(bind_addr).to_string_lossy().into_owned()),
+    };
+
+    np.bind_udp(bind_addr.as_deref(), port)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn netplay_local_port() -> i32 {
+    let np = get_netplay_intance().lock().unwrap();
+
+    np.local_port()
+}
+
+#[no_mangle]
+#[catch_status]
+pub unsafe extern "C" fn netplay_discovery_start() -> Status {
+    let mut np = get_netplay_intance().lock().unwrap();
+
+    np.start_discovery()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn netplay_discovery_stop() -> Status {
+    let mut np = get_netplay_intance().lock().unwrap();
+
+    np.stop_discovery();
+
+    Status::ok()
+}
+
+//Indexes stay valid for netplay_discovery_join as long as the list doesn't change
+#[no_mangle]
+pub unsafe extern "C" fn netplay_discovered_hosts() -> DiscoveredHosts {
+    let np = get_netplay_intance().lock().unwrap();
+
+    DiscoveredHosts::new(&np.discovered_hosts())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn netplay_discovered_hosts_free(hosts: DiscoveredHosts) {
+    if hosts.data.is_null() {
+        return;
+    }
+
+    let hosts = Vec::from_raw_parts(hosts.data, hosts.len, hosts.len);
+
+    for host in hosts {
+        status_info_free(host.name);
+        status_info_free(host.game_version);
+        status_info_free(host.addr);
+    }
+}
+
+#[no_mangle]
+#[catch_status]
+pub unsafe extern "C" fn netplay_discovery_join(config: SafeBytes, index: usize) -> Status {
+    let mut np = get_netplay_intance().lock().unwrap();
+
+    let safe_config = AppConfig::new(config);
+
+    np.join_discovered(safe_config, index)
+}
+
+#[no_mangle]
+#[catch_status]
+pub unsafe extern "C" fn netplay_lobby_open(config: SafeBytes) -> Status {
+    let mut np = get_netplay_intance().lock().unwrap();
+
+    let safe_config = AppConfig::new(config);
+
+    np.lobby_open(safe_config)
+}
+
+//Empty when no lobby is open
+#[no_mangle]
+pub unsafe extern "C" fn netplay_lobby_peers() -> LobbyPeers {
+    let mut np = get_netplay_intance().lock().unwrap();
+
+    match np.lobby_peers() {
+        Ok(peers) => LobbyPeers::new(&peers),
+        Err(_) => LobbyPeers::empty(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn netplay_lobby_peers_free(peers: LobbyPeers) {
+    if peers.data.is_null() {
+        return;
+    }
+
+    let peers = Vec::from_raw_parts(peers.data, peers.len, peers.len);
+
+    for peer in peers {
+        status_info_free(peer.id);
+        status_info_free(peer.name);
+    }
+}
+
+#[no_mangle]
+#[catch_status]
+pub unsafe extern "C" fn netplay_lobby_set_ready(ready: bool) -> Status {
+    let mut np = get_netplay_intance().lock().unwrap();
+
+    np.lobby_set_ready(ready)
+}
+
+#[no_mangle]
+#[catch_status]
+pub unsafe extern "C" fn netplay_lobby_assign_player(peer: *const c_char, handle: usize) -> Status {
+    let mut np = get_netplay_intance().lock().unwrap();
+
+    let peer = CStr::from_ptr(peer).to_string_lossy().into_owned();
+
+    np.lobby_assign(&peer, LobbyRole::Player(handle))
+}
+
+#[no_mangle]
+#[catch_status]
+pub unsafe extern "C" fn netplay_lobby_assign_spectator(peer: *const c_char) -> Status {
+    let mut np = get_netplay_intance().lock().unwrap();
+
+    let peer = CStr::from_ptr(peer).to_string_lossy().into_owned();
+
+    np.lobby_assign(&peer, LobbyRole::Spectator)
+}
+
+#[no_mangle]
+#[catch_status]
+pub unsafe extern "C" fn netplay_lobby_unassign(peer: *const c_char) -> Status {
+    let mut np = get_netplay_intance().lock().unwrap();
+
+    let peer = CStr::from_ptr(peer).to_string_lossy().into_owned();
+
+    np.lobby_assign(&peer, LobbyRole::Unassigned)
+}
+
+#[no_mangle]
+#[catch_status]
+pub unsafe extern "C" fn netplay_lobby_start() -> Status {
+    let mut np = get_netplay_intance().lock().unwrap();
+
+    np.lobby_start()
+}
+
+#[no_mangle]
+#[catch_status]
+pub unsafe extern "C" fn netplay_lobby_leave() -> Status {
+    let mut np = get_netplay_intance().lock().unwrap();
+
+    np.lobby_leave()
+}
+
+//Players and spectators this peer is connected to, names show up as peers send their metadata
+#[no_mangle]
+pub unsafe extern "C" fn netplay_players() -> PlayerInfos {
+    let np = get_netplay_intance().lock().unwrap();
+
+    PlayerInfos::new(&np.players())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn netplay_players_free(players: PlayerInfos) {
+    if players.data.is_null() {
+        return;
+    }
+
+    let players = Vec::from_raw_parts(players.data, players.len, players.len);
+
+    for player in players {
+        status_info_free(player.address);
+        status_info_free(player.name);
+        status_info_free(player.mod_version);
+    }
+}
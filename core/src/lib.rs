@@ -1,3 +1,6 @@
+//Every export is an unsafe extern "C" fn called by the host
+#![allow(clippy::missing_safety_doc)]
+
 use exts::MutexNetplayExtensions;
 use neplay::Netplay;
 use once_cell::sync::{Lazy, OnceCell};
@@ -17,7 +20,7 @@ static NETPLAY_HAS_DISCONNECTED: Lazy<Mutex<bool>> = Lazy::new(|| Mutex::new(fal
 static SHOULD_STOP_MATCHBOX_FUTURE: Lazy<Mutex<bool>> = Lazy::new(|| Mutex::new(false));
 
 unsafe fn get_netplay_intance() -> &'static Mutex<Netplay> {
-    let mutex = NETPLAY_INSTANCE.get_or_init(|| {
+    let mutex = (*std::ptr::addr_of!(NETPLAY_INSTANCE)).get_or_init(|| {
         tracing_subscriber::fmt()
             .compact()
             .with_thread_names(true)
@@ -34,16 +37,15 @@ unsafe fn get_netplay_intance() -> &'static Mutex<Netplay> {
 }
 
 unsafe fn reset_netplay_instance() {
-    NETPLAY_INSTANCE.take();
+    let instance = &mut *std::ptr::addr_of_mut!(NETPLAY_INSTANCE);
 
-    match NETPLAY_INSTANCE.set(Mutex::new(Netplay::new(None))) {
-        Ok(_) => {}
-        Err(_) => {}
-    }
+    instance.take();
+
+    let _ = instance.set(Mutex::new(Netplay::new(None)));
 }
 
 fn has_netplay_disconnected() -> bool {
-    NETPLAY_HAS_DISCONNECTED.lock().unwrap().clone()
+    *NETPLAY_HAS_DISCONNECTED.lock().unwrap()
 }
 
 fn set_netplay_disconnected(disconnected: bool) {
@@ -0,0 +1,6 @@
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub offset: usize,
+    pub len: usize,
+}
@@ -0,0 +1,48 @@
+use std::{ffi::CString, mem::forget, os::raw::c_char};
+
+use crate::core::discovery::DiscoveredHost;
+
+#[repr(C)]
+pub struct DiscoveredHostFFI {
+    pub name: *mut c_char,
+    pub game_version: *mut c_char,
+    pub addr: *mut c_char,
+    pub num_players: u32,
+    pub free_slots: u32,
+}
+
+#[repr(C)]
+pub struct DiscoveredHosts {
+    pub data: *mut DiscoveredHostFFI,
+    pub len: usize,
+}
+
+impl DiscoveredHosts {
+    pub fn new(hosts: &[DiscoveredHost]) -> Self {
+        let mut hosts: Vec<DiscoveredHostFFI> = hosts
+            .iter()
+            .map(|host| DiscoveredHostFFI {
+                name: CString::new(host.announcement.name.clone())
+                    .unwrap_or_default()
+                    .into_raw(),
+                game_version: CString::new(host.announcement.game_version.clone())
+                    .unwrap_or_default()
+                    .into_raw(),
+                addr: CString::new(host.addr.to_string())
+                    .unwrap_or_default()
+                    .into_raw(),
+                num_players: host.announcement.num_players,
+                free_slots: host.announcement.free_slots,
+            })
+            .collect();
+
+        hosts.shrink_to_fit();
+
+        let len = hosts.len();
+        let data = hosts.as_mut_ptr();
+
+        forget(hosts);
+
+        Self { data, len }
+    }
+}
@@ -0,0 +1,61 @@
+use std::{
+    ffi::{CStr, CString},
+    mem::forget,
+    os::raw::c_char,
+};
+
+use crate::{core::unmanaged::safe_bytes::SafeBytes, model::state_section::StateSection};
+
+#[repr(C)]
+pub struct GameStateSection {
+    pub name: *const c_char,
+    pub data: SafeBytes,
+}
+
+impl GameStateSection {
+    pub unsafe fn read(&self) -> (String, Vec<u8>) {
+        let name = match self.name.is_null() {
+            true => String::new(),
+            false => CStr::from_ptr(self.name).to_string_lossy().into_owned(),
+        };
+
+        (name, self.data.slice().to_vec())
+    }
+}
+
+#[repr(C)]
+pub struct GameStateSectionLayout {
+    pub name: *mut c_char,
+    pub offset: usize,
+    pub len: usize,
+}
+
+#[repr(C)]
+pub struct GameStateSectionLayouts {
+    pub data: *mut GameStateSectionLayout,
+    pub len: usize,
+}
+
+impl GameStateSectionLayouts {
+    pub fn new(sections: &[StateSection]) -> Self {
+        let mut layouts: Vec<GameStateSectionLayout> = sections
+            .iter()
+            .map(|section| GameStateSectionLayout {
+                name: CString::new(section.name.clone())
+                    .unwrap_or_default()
+                    .into_raw(),
+                offset: section.offset,
+                len: section.len,
+            })
+            .collect();
+
+        layouts.shrink_to_fit();
+
+        let len = layouts.len();
+        let data = layouts.as_mut_ptr();
+
+        forget(layouts);
+
+        Self { data, len }
+    }
+}
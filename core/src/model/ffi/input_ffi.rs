@@ -15,8 +15,7 @@ impl Inputs {
         let clone = inputs.clone();
         let requests = clone.as_ptr();
 
-        forget(requests); //TODO: swith to box
-        forget(clone);
+        forget(clone); //TODO: swith to box
 
         Self {
             data: requests,
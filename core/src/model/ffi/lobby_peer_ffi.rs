@@ -0,0 +1,58 @@
+use std::{ffi::CString, mem::forget, os::raw::c_char};
+
+use crate::core::lobby::{LobbyPeer, LobbyRole};
+
+#[repr(C)]
+pub struct LobbyPeerFFI {
+    pub id: *mut c_char,
+    pub name: *mut c_char,
+    pub ready: bool,
+    pub handle: i32, //-1 when not assigned a player slot
+    pub is_spectator: bool,
+    pub is_local: bool,
+}
+
+#[repr(C)]
+pub struct LobbyPeers {
+    pub data: *mut LobbyPeerFFI,
+    pub len: usize,
+}
+
+impl LobbyPeers {
+    pub fn new(peers: &[LobbyPeer]) -> Self {
+        let mut peers: Vec<LobbyPeerFFI> = peers
+            .iter()
+            .map(|peer| LobbyPeerFFI {
+                id: CString::new(peer.id.0.to_string())
+                    .unwrap_or_default()
+                    .into_raw(),
+                name: CString::new(peer.metadata.display_name.clone())
+                    .unwrap_or_default()
+                    .into_raw(),
+                ready: peer.ready,
+                handle: match peer.role {
+                    LobbyRole::Player(handle) => handle as i32,
+                    _ => -1,
+                },
+                is_spectator: peer.role == LobbyRole::Spectator,
+                is_local: peer.is_local,
+            })
+            .collect();
+
+        peers.shrink_to_fit();
+
+        let len = peers.len();
+        let data = peers.as_mut_ptr();
+
+        forget(peers);
+
+        Self { data, len }
+    }
+
+    pub fn empty() -> Self {
+        Self {
+            data: std::ptr::null_mut(),
+            len: 0,
+        }
+    }
+}
@@ -1,2 +1,6 @@
+pub mod discovered_host_ffi;
+pub mod game_state_section_ffi;
 pub mod input_ffi;
+pub mod lobby_peer_ffi;
 pub mod netplay_request_ffi;
+pub mod player_info_ffi;
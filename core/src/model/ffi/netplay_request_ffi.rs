@@ -14,8 +14,7 @@ impl NetplayRequests {
         let clone = netplay_requests.clone();
         let requests = clone.as_ptr();
 
-        forget(requests); //TODO: swith to box
-        forget(clone);
+        forget(clone); //TODO: swith to box
 
         Self {
             data: requests,
@@ -0,0 +1,64 @@
+use std::{ffi::CString, mem::forget, os::raw::c_char};
+
+use crate::{
+    core::player_registry::PlayerEntry,
+    model::player_info::{ConnectionState, PlayerKind},
+};
+
+#[repr(C)]
+pub struct PlayerInfo {
+    pub handle: i32, //-1 for the spectated host and the local spectator
+    pub kind: PlayerKind,
+    pub address: *mut c_char, //Empty for local players
+    pub name: *mut c_char,    //Empty until the peer sent its metadata
+    pub archer_color: i32,
+    pub mod_version: *mut c_char,
+    pub has_metadata: bool,
+    pub state: ConnectionState,
+}
+
+#[repr(C)]
+pub struct PlayerInfos {
+    pub data: *mut PlayerInfo,
+    pub len: usize,
+}
+
+impl PlayerInfos {
+    pub fn new(entries: &[PlayerEntry]) -> Self {
+        let mut infos: Vec<PlayerInfo> = entries
+            .iter()
+            .map(|entry| {
+                let metadata = entry.metadata.clone().unwrap_or_default();
+                let address = entry
+                    .address
+                    .as_ref()
+                    .map(|address| address.to_string())
+                    .unwrap_or_default();
+
+                PlayerInfo {
+                    handle: entry.handle.map_or(-1, |handle| handle as i32),
+                    kind: entry.kind,
+                    address: CString::new(address).unwrap_or_default().into_raw(),
+                    name: CString::new(metadata.display_name)
+                        .unwrap_or_default()
+                        .into_raw(),
+                    archer_color: metadata.archer_color,
+                    mod_version: CString::new(metadata.mod_version)
+                        .unwrap_or_default()
+                        .into_raw(),
+                    has_metadata: entry.metadata.is_some(),
+                    state: entry.state,
+                }
+            })
+            .collect();
+
+        infos.shrink_to_fit();
+
+        let len = infos.len();
+        let data = infos.as_mut_ptr();
+
+        forget(infos);
+
+        Self { data, len }
+    }
+}
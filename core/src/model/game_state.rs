@@ -1,11 +1,33 @@
 use serde::{Deserialize, Serialize};
 
-use crate::core::unmanaged::{safe_bytes::SafeBytes, unmanaged_bytes::UnmanagedBytes};
+use crate::{
+    core::unmanaged::{safe_bytes::SafeBytes, unmanaged_bytes::UnmanagedBytes},
+    model::state_section::StateSection,
+};
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq)]
+pub enum StateEncoding {
+    #[default]
+    Raw,
+    Deflate {
+        raw_size: usize,
+    },
+    Keyframe {
+        frame: i32,
+    },
+    Delta {
+        keyframe: i32,
+        raw_size: usize,
+        deflated: bool,
+    },
+}
 
 #[derive(Clone, Serialize, Deserialize, Debug, Default)]
 pub struct GameState {
     data: UnmanagedBytes,
     frame: i32,
+    encoding: StateEncoding,
+    sections: Vec<StateSection>,
 }
 
 impl GameState {
@@ -13,6 +35,17 @@ impl GameState {
         Self {
             data: UnmanagedBytes::new(safe_bytes),
             frame: 0,
+            encoding: StateEncoding::Raw,
+            sections: vec![],
+        }
+    }
+
+    pub fn encoded(bytes: Vec<u8>, encoding: StateEncoding) -> Self {
+        Self {
+            data: UnmanagedBytes::from_vec(bytes),
+            frame: 0,
+            encoding,
+            sections: vec![],
         }
     }
 
@@ -20,6 +53,8 @@ impl GameState {
         Self {
             data: UnmanagedBytes::empty(),
             frame: 0,
+            encoding: StateEncoding::Raw,
+            sections: vec![],
         }
     }
 
@@ -27,6 +62,22 @@ impl GameState {
         self.data.clone()
     }
 
+    pub fn bytes(&self) -> &[u8] {
+        self.data.as_slice()
+    }
+
+    pub fn encoding(&self) -> StateEncoding {
+        self.encoding
+    }
+
+    pub fn sections(&self) -> &[StateSection] {
+        &self.sections
+    }
+
+    pub fn set_sections(&mut self, sections: Vec<StateSection>) {
+        self.sections = sections;
+    }
+
     pub unsafe fn release(&mut self) {
         self.data = UnmanagedBytes::empty();
     }
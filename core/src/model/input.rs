@@ -1,5 +1,6 @@
 use super::vector2f::Vector2f;
 use bytemuck::{Pod, Zeroable};
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 
 #[repr(C)]
@@ -19,3 +20,38 @@ pub struct Input {
     aim_axis: Vector2f,
     aim_right_axis: Vector2f,
 }
+
+impl Input {
+    pub fn random<R: Rng>(rng: &mut R) -> Self {
+        let move_x: isize = rng.gen_range(-1..=1);
+        let move_y: isize = rng.gen_range(-1..=1);
+        let jump = rng.gen_bool(0.2) as usize;
+        let shoot = rng.gen_bool(0.1) as usize;
+        let alt_shoot = rng.gen_bool(0.05) as usize;
+        let dodge = rng.gen_bool(0.1) as usize;
+        let aim = Vector2f {
+            x: move_x as f32,
+            y: move_y as f32,
+        };
+
+        Self {
+            jump_check: jump,
+            jump_pressed: jump,
+            shoot_check: shoot,
+            shoot_pressed: shoot,
+            alt_shoot_check: alt_shoot,
+            alt_shoot_pressed: alt_shoot,
+            dodge_check: dodge,
+            dodge_pressed: dodge,
+            arrow_pressed: rng.gen_bool(0.05) as usize,
+            move_x: move_x as usize,
+            move_y: move_y as usize,
+            aim_axis: aim,
+            aim_right_axis: Vector2f::default(),
+        }
+    }
+}
+
+//Called with the handle, the frame and the last confirmed input of the disconnected player
+pub type DisconnectedInputCallback =
+    extern "C" fn(handle: i32, frame: i32, last_confirmed: Input) -> Input;
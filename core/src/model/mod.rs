@@ -1,6 +1,11 @@
+pub mod byte_range;
 pub mod ffi;
 pub mod game_state;
 pub mod input;
 pub mod netplay_request;
 pub mod network_stats;
+pub mod player_info;
+pub mod state_section;
+pub mod state_stats;
+pub mod synctest_report;
 pub mod vector2f;
@@ -0,0 +1,16 @@
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PlayerKind {
+    Local = 0,
+    Remote = 1,
+    Spectator = 2,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connecting = 0,
+    Connected = 1,
+    Interrupted = 2,
+    Disconnected = 3,
+}
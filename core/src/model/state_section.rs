@@ -0,0 +1,31 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct StateSection {
+    pub name: String,
+    pub offset: usize,
+    pub len: usize,
+    pub checksum: u128,
+}
+
+//Names of the sections whose checksum differ or that only exist on one side
+pub fn diverged_sections(local: &[StateSection], remote: &[StateSection]) -> Vec<String> {
+    let mut diverged: Vec<String> = local
+        .iter()
+        .filter(|section| {
+            !remote
+                .iter()
+                .any(|other| other.name == section.name && other.checksum == section.checksum)
+        })
+        .map(|section| section.name.clone())
+        .collect();
+
+    for section in remote {
+        if !local.iter().any(|other| other.name == section.name) {
+            diverged.push(section.name.clone());
+        }
+    }
+
+    diverged
+}
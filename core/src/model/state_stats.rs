@@ -0,0 +1,11 @@
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StateStats {
+    pub saved_states: u32,
+    pub loaded_states: u32,
+    pub raw_bytes: u64,
+    pub stored_bytes: u64,
+    pub compression_ratio: f32,
+    pub avg_encode_micros: f32,
+    pub avg_decode_micros: f32,
+}
@@ -0,0 +1,39 @@
+use std::{ffi::CString, os::raw::c_char};
+
+use crate::core::{synctest::SyncTestMismatch, unmanaged::safe_bytes::SafeBytes};
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct SyncTestReport {
+    pub frame: i32,
+    pub expected_checksum_low: u64,
+    pub expected_checksum_high: u64,
+    pub actual_checksum_low: u64,
+    pub actual_checksum_high: u64,
+    pub checksums_known: bool, //The checksums are zeroed otherwise
+    pub expected_state: SafeBytes,
+    pub actual_state: SafeBytes,
+    pub diverged_sections: *mut c_char, //Comma separated
+}
+
+impl SyncTestReport {
+    pub fn new(mismatch: SyncTestMismatch) -> Self {
+        Self {
+            frame: mismatch.frame,
+            expected_checksum_low: mismatch.expected_checksum as u64,
+            expected_checksum_high: (mismatch.expected_checksum >> 64) as u64,
+            actual_checksum_low: mismatch.actual_checksum as u64,
+            actual_checksum_high: (mismatch.actual_checksum >> 64) as u64,
+            checksums_known: mismatch.checksums_known,
+            expected_state: mismatch
+                .expected_state
+                .map_or(SafeBytes::empty(), SafeBytes::from_vec),
+            actual_state: mismatch
+                .actual_state
+                .map_or(SafeBytes::empty(), SafeBytes::from_vec),
+            diverged_sections: CString::new(mismatch.diverged_sections.join(","))
+                .unwrap_or_default()
+                .into_raw(),
+        }
+    }
+}
@@ -1,7 +1,8 @@
 use futures::{select, FutureExt};
 use futures_timer::Delay;
-use matchbox_socket::{PeerId, WebRtcSocket};
-use std::net::SocketAddr;
+use matchbox_socket::{ChannelConfig, MultipleChannels, PeerId, WebRtcChannel, WebRtcSocket};
+use std::collections::{BTreeMap, HashMap};
+use std::net::{SocketAddr, UdpSocket};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use tokio::runtime::Runtime;
@@ -9,25 +10,58 @@ use tracing::{error, info, warn};
 use uuid::Uuid;
 
 use ggrs::{
-    DesyncDetection, GGRSError, GGRSRequest, InputStatus, PlayerType, SessionBuilder,
-    SyncTestSession, UdpNonBlockingSocket,
+    DesyncDetection, GGRSError, GGRSRequest, InputStatus, NonBlockingSocket, PlayerType,
+    SessionBuilder, SyncTestSession,
 };
 
-use crate::core::unmanaged::safe_bytes::SafeBytes;
+use crate::core::{
+    checksum::{checksum, checksum_of, ChecksumSource},
+    discovery::{self, DiscoveredHost, HostAnnouncement},
+    forensics::{Forensics, StateChunk, CHUNK_SIZE, DIRECT_CHUNK_SIZE},
+    input_generator::InputGenerator,
+    lobby::{Lobby, LobbyPeer, LobbyRole},
+    network_simulator::NetworkSimulator,
+    packet_cipher::PacketCipher,
+    player_registry::{PlayerEntry, PlayerRegistry, SessionSettings},
+    rendezvous,
+    state_store::StateStore,
+    synctest::{SyncTestMismatch, SyncTestTracker},
+    unmanaged::safe_bytes::SafeBytes,
+};
 use crate::set_netplay_disconnected;
 use crate::{
     config::{
-        app_config::AppConfig,
-        ggrs_config::{Address, GGRSConfig},
+        app_config::{
+            AppConfig, ChecksumAlgorithm, DisconnectedInputPolicy, NetplayAnnounceConfig,
+            PlayerMetadata,
+        },
+        ggrs_config::{
+            Address, CompositeSocket, ControlKind, DirectUdpSocket, GGRSConfig, LoopbackSocket,
+            PacketGuard, PeerChannel, Transport,
+        },
     },
     model::{
-        game_state::GameState, input::Input, netplay_request::NetplayRequest,
+        game_state::{GameState, StateEncoding},
+        input::{DisconnectedInputCallback, Input},
+        netplay_request::NetplayRequest,
         network_stats::NetworkStats,
+        player_info::{ConnectionState, PlayerKind},
+        state_section::StateSection,
+        state_stats::StateStats,
     },
     session::{Session, SessionType},
     SHOULD_STOP_MATCHBOX_FUTURE,
 };
 
+const CONFIRMED_INPUTS_DEPTH: i32 = 128;
+const MAX_PREDICTION_WINDOW: usize = 10;
+const DESYNC_DETECTION_INTERVAL: u32 = 500;
+const DISCONNECT_TIMEOUT_SECS: u64 = 7;
+const MAX_FRAMES_BEHIND: usize = 50;
+const CATCHUP_SPEED: usize = 4;
+const RENDEZVOUS_TIMEOUT_SECS: u64 = 20;
+const JOIN_TIMEOUT_SECS: u64 = 10;
+
 pub struct Netplay {
     pub local_player_handle: Option<usize>,
     pub remote_player_handle: Option<usize>,
@@ -39,6 +73,25 @@ pub struct Netplay {
     game_state: GameState,
     current_inputs: Option<Vec<Input>>,
     current_remote_players: Option<Vec<Address>>,
+    disconnected_input_policy: DisconnectedInputPolicy,
+    disconnected_input_callback: Option<DisconnectedInputCallback>,
+    confirmed_inputs: HashMap<usize, BTreeMap<i32, Input>>,
+    disconnected_at: HashMap<usize, i32>,
+    state_store: StateStore,
+    checksum_algorithm: ChecksumAlgorithm,
+    config: Option<AppConfig>,
+    forensics: Option<Forensics>,
+    forensics_channel: Option<WebRtcChannel>,
+    packet_guard: PacketGuard,
+    prebound_socket: Option<UdpSocket>,
+    local_port: Option<u16>,
+    pending_events: Vec<&'static str>,
+    synctest: Option<SyncTestTracker>,
+    test_host_handles: Vec<usize>,
+    input_generators: Vec<(usize, InputGenerator)>,
+    discovery: Option<discovery::Listener>,
+    lobby: Option<Lobby>,
+    players: PlayerRegistry,
 }
 
 impl Netplay {
@@ -54,6 +107,25 @@ impl Netplay {
             game_state: GameState::empty(),
             current_inputs: Some(vec![]),
             current_remote_players: Some(vec![]),
+            disconnected_input_policy: DisconnectedInputPolicy::Neutral,
+            disconnected_input_callback: None,
+            confirmed_inputs: HashMap::new(),
+            disconnected_at: HashMap::new(),
+            state_store: StateStore::default(),
+            checksum_algorithm: ChecksumAlgorithm::default(),
+            config: None,
+            forensics: None,
+            forensics_channel: None,
+            packet_guard: PacketGuard::default(),
+            prebound_socket: None,
+            local_port: None,
+            pending_events: vec![],
+            synctest: None,
+            test_host_handles: vec![],
+            input_generators: vec![],
+            discovery: None,
+            lobby: None,
+            players: PlayerRegistry::default(),
         }
     }
 
@@ -85,6 +157,268 @@ impl Netplay {
         }
     }
 
+    //Binds the direct UDP socket ahead of init, so the real port is known before the session starts.
+    //LocalConf sessions and spectators of a UDP host use it, matchbox sessions bind their own
+    pub fn bind_udp(&mut self, bind_addr: Option<&str>, port: u16) -> Result<u16, String> {
+        let socket = DirectUdpSocket::bind(bind_addr, port)?;
+        let port = socket
+            .local_addr()
+            .map_err(|e| format!("Couldn't read UDP socket address : {}", e))?
+            .port();
+
+        self.prebound_socket = Some(socket);
+        self.local_port = Some(port);
+
+        Ok(port)
+    }
+
+    pub fn local_port(&self) -> i32 {
+        match self.local_port {
+            Some(port) => port as i32,
+            None => -1,
+        }
+    }
+
+    //Listens for hosts announced on the LAN until stopped or a host is joined
+    pub fn start_discovery(&mut self) -> Result<(), String> {
+        if self.discovery.is_none() {
+            self.discovery = Some(discovery::Listener::start()?);
+        }
+
+        Ok(())
+    }
+
+    pub fn stop_discovery(&mut self) {
+        self.discovery = None;
+    }
+
+    pub fn discovered_hosts(&self) -> Vec<DiscoveredHost> {
+        match self.discovery {
+            Some(ref listener) => listener.hosts(),
+            None => vec![],
+        }
+    }
+
+    //Starts a direct UDP session with a host from discovered_hosts, the config only needs its local conf
+    pub unsafe fn join_discovered(
+        &mut self,
+        mut config: AppConfig,
+        index: usize,
+    ) -> Result<(), String> {
+        let host = self
+            .discovered_hosts()
+            .get(index)
+            .cloned()
+            .ok_or(format!("No discovered host at {}", index))?;
+
+        if host.announcement.free_slots == 0 {
+            return Err(format!("{} is full", host.announcement.name));
+        }
+
+        let mut local = config
+            .netplay
+            .local_conf
+            .take()
+            .ok_or("Joining a discovered host needs a local conf")?;
+
+        local.remote_addr = host.addr.to_string();
+        local.player_draw = match host.announcement.host_player_draw {
+            0 => 1,
+            _ => 0,
+        };
+        local.players.clear();
+        local.rendezvous = None;
+        local.announce = None;
+        local.join_announced = true;
+
+        config.netplay.num_players = host.announcement.num_players as i32;
+        config.netplay.local_conf = Some(local);
+
+        self.stop_discovery();
+        self.init(config)
+    }
+
+    //Joins the matchbox room of the server conf without starting a session, see lobby_start
+    pub fn lobby_open(&mut self, config: AppConfig) -> Result<(), String> {
+        if self.lobby.is_some() {
+            return Err("A lobby is already open".to_string());
+        }
+
+        let server = config
+            .netplay
+            .server_conf
+            .clone()
+            .ok_or("A lobby needs a server conf")?;
+        let room_url = server.room_url.ok_or("A lobby needs a room url")?;
+
+        self.configure(&config);
+
+        let exchange_desync_states = self
+            .forensics
+            .as_ref()
+            .is_some_and(|forensics| forensics.should_exchange());
+
+        self.lobby = Some(Lobby::open(
+            room_url,
+            server.is_host,
+            config.netplay.local_metadata(),
+            exchange_desync_states,
+        )?);
+
+        info!("Lobby open");
+
+        Ok(())
+    }
+
+    pub fn lobby_peers(&mut self) -> Result<Vec<LobbyPeer>, String> {
+        let lobby = self.lobby.as_mut().ok_or("No lobby open")?;
+
+        self.pending_events.extend(lobby.update());
+
+        Ok(lobby.peers())
+    }
+
+    pub fn lobby_set_ready(&mut self, ready: bool) -> Result<(), String> {
+        let lobby = self.lobby.as_mut().ok_or("No lobby open")?;
+
+        self.pending_events.extend(lobby.update());
+        lobby.set_ready(ready);
+
+        Ok(())
+    }
+
+    //Host only, a player slot already taken moves to the new peer
+    pub fn lobby_assign(&mut self, peer: &str, role: LobbyRole) -> Result<(), String> {
+        let num_players = self
+            .config
+            .as_ref()
+            .map_or(0, |config| config.netplay.num_players as usize);
+        let lobby = self.lobby.as_mut().ok_or("No lobby open")?;
+
+        self.pending_events.extend(lobby.update());
+        lobby.assign(peer, role, num_players)
+    }
+
+    //The host starts once every slot has a ready peer, the others once the host did (LobbyStarted event)
+    pub fn lobby_start(&mut self) -> Result<(), String> {
+        let config = self.config.clone().ok_or("No lobby open")?;
+        let num_players = config.netplay.num_players as usize;
+        let lobby = self.lobby.as_mut().ok_or("No lobby open")?;
+
+        self.pending_events.extend(lobby.update());
+
+        let roles = lobby.start(num_players)?;
+        let local_id = lobby
+            .local_id()
+            .ok_or("Not connected to the signaling server")?;
+        let host = lobby.host().ok_or("No host in the lobby")?;
+        let is_host = lobby.is_host();
+        let metadata: Vec<(PeerId, PlayerMetadata)> = lobby
+            .peers()
+            .into_iter()
+            .filter(|peer| !peer.is_local)
+            .map(|peer| (peer.id, peer.metadata))
+            .collect();
+
+        let local_role = roles
+            .iter()
+            .find(|(id, _)| *id == local_id)
+            .map_or(LobbyRole::Unassigned, |(_, role)| *role);
+
+        match local_role {
+            LobbyRole::Player(_) => {
+                let players = roles
+                    .iter()
+                    .filter_map(|(id, role)| match role {
+                        LobbyRole::Player(handle) if *id == local_id => Some((*handle, None)),
+                        LobbyRole::Player(handle) => Some((*handle, Some(Address::Peer(*id)))),
+                        _ => None,
+                    })
+                    .collect();
+                //Only the host sends inputs to spectators
+                let spectators = match is_host {
+                    true => roles
+                        .iter()
+                        .filter(|(_, role)| *role == LobbyRole::Spectator)
+                        .map(|(id, _)| Address::Peer(*id))
+                        .collect(),
+                    false => vec![],
+                };
+
+                //The channel stays in the lobby until the session is sure to start
+                let builder = self.direct_session_builder(&config, players, spectators)?;
+                let socket = self.take_lobby_socket()?;
+                let session = builder
+                    .start_p2p_session(NetworkSimulator::new(
+                        socket,
+                        config.network_simulation.clone(),
+                    ))
+                    .map_err(|e| format!("Couldn't start p2p session : {}", e))?;
+
+                self.session = Some(SessionType::P2P(session));
+
+                info!("Starting lobby p2p session");
+            }
+            LobbyRole::Spectator => {
+                let socket = self.take_lobby_socket()?;
+                let session = SessionBuilder::<GGRSConfig>::new()
+                    .with_num_players(num_players)
+                    .with_input_delay(config.input_delay as usize)
+                    .with_max_prediction_window(MAX_PREDICTION_WINDOW)
+                    .start_spectator_session(
+                        Address::Peer(host),
+                        NetworkSimulator::new(socket, config.network_simulation.clone()),
+                    );
+
+                self.current_remote_players = Some(vec![Address::Peer(host)]);
+                self.players.add(None, PlayerKind::Spectator, None);
+                self.players
+                    .add(None, PlayerKind::Remote, Some(Address::Peer(host)));
+                self.session = Some(SessionType::Spectate(session));
+                self.is_spectator = true;
+
+                info!("Spectating {} from the lobby", host.0);
+            }
+            LobbyRole::Unassigned => {
+                return Err("The host didn't assign a slot to this peer".to_string());
+            }
+        }
+
+        for (id, metadata) in metadata {
+            self.players.set_metadata(&Address::Peer(id), metadata);
+        }
+
+        Ok(())
+    }
+
+    fn take_lobby_socket(&mut self) -> Result<PeerChannel, String> {
+        let lobby = self.lobby.as_mut().ok_or("No lobby open")?;
+        let channel = lobby
+            .take_ggrs_channel()
+            .ok_or("The lobby session already started")?;
+
+        self.forensics_channel = lobby.take_forensics_channel();
+
+        Ok(PeerChannel::new(channel, self.packet_guard.clone()))
+    }
+
+    //Leaves the lobby, or the session started from it
+    pub fn lobby_leave(&mut self) -> Result<(), String> {
+        if self.session.is_some() {
+            return self.reset();
+        }
+
+        self.lobby.take().ok_or("No lobby open")?;
+        self.config = None;
+        self.forensics = None;
+
+        if let Ok(mut stp) = SHOULD_STOP_MATCHBOX_FUTURE.try_lock() {
+            *stp = true;
+        }
+
+        Ok(())
+    }
+
     pub fn reset(&mut self) -> Result<(), String> {
         let session_res = self.session();
 
@@ -97,14 +431,26 @@ impl Netplay {
             self.game_state = GameState::empty();
             self.current_inputs = Some(vec![]);
             self.current_remote_players = Some(vec![]);
+            self.confirmed_inputs.clear();
+            self.disconnected_at.clear();
+            self.state_store = StateStore::default();
+            self.config = None;
+            self.forensics = None;
+            self.forensics_channel = None;
+            self.packet_guard = PacketGuard::default();
+            self.prebound_socket = None;
+            self.local_port = None;
+            self.pending_events.clear();
+            self.synctest = None;
+            self.test_host_handles.clear();
+            self.input_generators.clear();
+            self.lobby = None;
+            self.players = PlayerRegistry::default();
             self.is_test = false;
             self.session = None;
 
-            match SHOULD_STOP_MATCHBOX_FUTURE.try_lock() {
-                Ok(mut stp) => {
-                    *stp = true;
-                }
-                Err(_) => {}
+            if let Ok(mut stp) = SHOULD_STOP_MATCHBOX_FUTURE.try_lock() {
+                *stp = true;
             }
 
             set_netplay_disconnected(true);
@@ -112,7 +458,7 @@ impl Netplay {
             return Ok(());
         }
 
-        return Err("reset : No session found".to_string());
+        Err("reset : No session found".to_string())
     }
 
     pub fn session(&mut self) -> Option<Box<dyn Session<GGRSConfig>>> {
@@ -127,24 +473,198 @@ impl Netplay {
     }
 
     //TODO: refactor this
+    fn configure(&mut self, config: &AppConfig) {
+        self.is_test = config.is_test();
+        self.synctest = config
+            .test
+            .as_ref()
+            .map(|test| SyncTestTracker::new(test.check_distance as usize, test.capture_states));
+        self.disconnected_input_policy = config.disconnected_input_policy;
+        self.state_store = StateStore::new(&config.state_storage, MAX_PREDICTION_WINDOW);
+        self.checksum_algorithm = config.checksum;
+        self.forensics = config
+            .desync_dump
+            .as_ref()
+            .map(|dump| Forensics::new(dump, DESYNC_DETECTION_INTERVAL));
+        self.config = Some(config.clone());
+        self.packet_guard = PacketGuard::new(config.max_packet_size);
+        self.players = PlayerRegistry::new(
+            config.netplay.local_metadata(),
+            SessionSettings {
+                disconnected_input_policy: config.disconnected_input_policy,
+                checksum: config.checksum,
+            },
+        );
+
+        if self.disconnected_input_policy == DisconnectedInputPolicy::Host
+            && self.disconnected_input_callback.is_none()
+        {
+            warn!("Host disconnected input policy without callback, neutral input will be used");
+        }
+    }
+
+    //Two players session over an in-process loopback network, for tests
+    pub fn init_with_loopback(
+        &mut self,
+        config: AppConfig,
+        socket: LoopbackSocket,
+        remote_addr: Address,
+        player_draw: u32,
+    ) -> Result<(), String> {
+        self.configure(&config);
+        self.start_direct_session(
+            &config,
+            socket.with_guard(self.packet_guard.clone()),
+            two_players(remote_addr, player_draw),
+            vec![],
+        )
+    }
+
+    //Players are (handle, address), no address for the local one. Spectators take the handles after the players
+    fn start_direct_session<S: NonBlockingSocket<Address> + 'static>(
+        &mut self,
+        config: &AppConfig,
+        socket: S,
+        players: Vec<(usize, Option<Address>)>,
+        spectators: Vec<Address>,
+    ) -> Result<(), String> {
+        let session = self
+            .direct_session_builder(config, players, spectators)?
+            .start_p2p_session(NetworkSimulator::new(
+                socket,
+                config.network_simulation.clone(),
+            ))
+            .map_err(|e| format!("Couldn't start p2p session : {}", e))?;
+
+        self.session = Some(SessionType::P2P(session));
+
+        Ok(())
+    }
+
+    //Every check happens here, so starting the session from the builder can't fail and lose the socket
+    fn direct_session_builder(
+        &mut self,
+        config: &AppConfig,
+        mut players: Vec<(usize, Option<Address>)>,
+        spectators: Vec<Address>,
+    ) -> Result<SessionBuilder<GGRSConfig>, String> {
+        players.sort_by_key(|(handle, _)| *handle);
+
+        let num_players = players.len();
+        let locals = players.iter().filter(|(_, addr)| addr.is_none()).count();
+
+        if locals != 1 {
+            return Err(format!(
+                "Direct session needs exactly one local player, got {}",
+                locals
+            ));
+        }
+
+        if let Some((expected, (handle, _))) = players
+            .iter()
+            .enumerate()
+            .find(|(expected, (handle, _))| expected != handle)
+        {
+            return Err(format!(
+                "Player handles must go from 0 to {}, found {} instead of {}",
+                num_players - 1,
+                handle,
+                expected
+            ));
+        }
+
+        let mut builder = p2p_session_builder(config).with_num_players(num_players);
+
+        for (handle, addr) in players {
+            match addr {
+                None => {
+                    self.local_player_handle = Some(handle);
+                    self.players.add(Some(handle), PlayerKind::Local, None);
+                    builder = builder
+                        .add_player(PlayerType::Local, handle)
+                        .map_err(|e| format!("Couldn't add local player {} : {}", handle, e))?;
+                }
+                Some(addr) => {
+                    if self.remote_player_handle.is_none() {
+                        self.remote_player_handle = Some(handle);
+                    }
+
+                    self.current_remote_players
+                        .get_or_insert(vec![])
+                        .push(addr.clone());
+                    self.players
+                        .add(Some(handle), PlayerKind::Remote, Some(addr.clone()));
+                    builder = builder
+                        .add_player(PlayerType::Remote(addr), handle)
+                        .map_err(|e| format!("Couldn't add remote player {} : {}", handle, e))?;
+                }
+            }
+        }
+
+        for (i, addr) in spectators.into_iter().enumerate() {
+            let handle = num_players + i;
+
+            self.spectators_handles.push(handle);
+            self.players
+                .add(Some(handle), PlayerKind::Spectator, Some(addr.clone()));
+            builder = builder
+                .add_player(PlayerType::Spectator(addr), handle)
+                .map_err(|e| format!("Couldn't add spectator {} : {}", handle, e))?;
+        }
+
+        Ok(builder)
+    }
+
     pub unsafe fn init(&mut self, config: AppConfig) -> Result<(), String> {
-        let mut session = SessionBuilder::<GGRSConfig>::new()
-            .with_input_delay(config.input_delay as usize)
-            .with_max_prediction_window(10)
-            .with_disconnect_timeout(Duration::from_secs(7))
-            .with_max_frames_behind(50)
-            .unwrap()
-            .with_catchup_speed(4)
-            .unwrap();
+        let mut session = p2p_session_builder(&config);
+
+        self.configure(&config);
 
-        self.is_test = config.is_test();
         let players_from_config = config.clone().netplay.players.unwrap();
-        let spectator_from_config = config.clone().netplay.spectators.clone().unwrap_or(vec![]);
+        //Spectators given as socket addresses watch over direct UDP, the others are matchbox peers
+        let (udp_spectators, spectator_from_config): (Vec<String>, Vec<String>) = config
+            .clone()
+            .netplay
+            .spectators
+            .unwrap_or(vec![])
+            .into_iter()
+            .partition(|spectator| spectator.parse::<SocketAddr>().is_ok());
         let max_players = config.netplay.num_players as usize + spectator_from_config.len();
 
         if let Some(spectate) = config.netplay.spectator_conf {
             session = session.with_num_players(config.netplay.num_players as usize);
 
+            if let Some(host_addr) = spectate.host_addr {
+                let socket = match self.prebound_socket.take() {
+                    Some(udp_socket) => {
+                        DirectUdpSocket::new(udp_socket, self.packet_guard.clone(), None)?
+                    }
+                    None => DirectUdpSocket::bind_to_port(
+                        spectate.port.unwrap_or(0),
+                        self.packet_guard.clone(),
+                        None,
+                    )?,
+                };
+                let host_addr = socket.resolve(&host_addr)?;
+
+                self.local_port = socket.local_port();
+
+                let sess = session.start_spectator_session(
+                    Address::Socket(host_addr),
+                    NetworkSimulator::new(socket, config.network_simulation.clone()),
+                );
+
+                info!("Spectating {} over UDP", host_addr);
+
+                self.players.add(None, PlayerKind::Spectator, None);
+                self.players
+                    .add(None, PlayerKind::Remote, Some(Address::Socket(host_addr)));
+                self.session = Some(SessionType::Spectate(sess));
+                self.is_spectator = true;
+
+                return Ok(());
+            }
+
             let (mut socket, future_msg) = WebRtcSocket::new_ggrs(spectate.room_url.unwrap());
 
             let channel = socket.take_channel(0).unwrap();
@@ -187,22 +707,16 @@ impl Netplay {
 
                         while !should_stop {
                             {
-                                match SHOULD_STOP_MATCHBOX_FUTURE.try_lock() {
-                                    Ok(stp) => {
-                                        should_stop = *stp;
-                                    }
-                                    Err(_) => {}
+                                if let Ok(stp) = SHOULD_STOP_MATCHBOX_FUTURE.try_lock() {
+                                    should_stop = *stp;
                                 }
                             }
 
                             socket.update_peers();
 
                             if !ignore_player_update {
-                                match clone_for_thread.lock() {
-                                    Ok(mut players) => {
-                                        *players = socket.players();
-                                    }
-                                    Err(_) => {}
+                                if let Ok(mut players) = clone_for_thread.lock() {
+                                    *players = socket.players();
                                 }
 
                                 ignore_player_update = socket.players().len() >= max_players;
@@ -219,12 +733,9 @@ impl Netplay {
                                     let mut can_go = false;
 
                                         while !can_go {
-                                            match SHOULD_STOP_MATCHBOX_FUTURE.try_lock() {
-                                                Ok(mut stp) => {
-                                                    *stp = true;
-                                                    can_go = true;
-                                                }
-                                                Err(_) => {}
+                                            if let Ok(mut stp) = SHOULD_STOP_MATCHBOX_FUTURE.try_lock() {
+                                                *stp = true;
+                                                can_go = true;
                                             }
                                         }
 
@@ -254,19 +765,13 @@ impl Netplay {
                 }
 
                 {
-                    match SHOULD_STOP_MATCHBOX_FUTURE.try_lock() {
-                        Ok(stp) => {
-                            should_stop = *stp;
-                        }
-                        Err(_) => {}
+                    if let Ok(stp) = SHOULD_STOP_MATCHBOX_FUTURE.try_lock() {
+                        should_stop = *stp;
                     }
                 }
 
-                match clone.try_lock() {
-                    Ok(pl) => {
-                        players_connected = pl.clone();
-                    }
-                    Err(_) => {}
+                if let Ok(pl) = clone.try_lock() {
+                    players_connected = pl.clone();
                 }
 
                 if players_connected.len()
@@ -298,37 +803,51 @@ impl Netplay {
             if players_connected.len()
                 == config.netplay.num_players as usize + spectator_from_config.len()
             {
-                //Add players by index
-                let mut index = 0;
-                for (_, player) in players_connected_typed.clone().into_iter().enumerate() {
-                    match player {
-                        PlayerType::Remote(peer_id) => {
-                            match self.current_remote_players {
-                                Some(ref mut players) => {
-                                    players.push(Address::Peer(peer_id));
-                                }
-                                None => {
-                                    self.current_remote_players =
-                                        Some(vec![Address::Peer(peer_id)]);
-                                }
-                            }
+                let host_peer =
+                    PeerId(Uuid::parse_str(&spectate.to_spectate.clone().unwrap()).unwrap());
+                let mut host_handle = None;
 
-                            if self.local_player_handle.is_none() {
-                                self.local_player_handle = Some(index);
-                            } else {
-                                self.remote_player_handle = Some(index);
+                //Same handles as the players give themselves, they follow the peer ids
+                for (index, player) in players_by_handle(&players_connected_typed, None)
+                    .into_iter()
+                    .enumerate()
+                {
+                    if let PlayerType::Remote(peer_id) = player {
+                        match self.current_remote_players {
+                            Some(ref mut players) => {
+                                players.push(Address::Peer(peer_id));
+                            }
+                            None => {
+                                self.current_remote_players = Some(vec![Address::Peer(peer_id)]);
                             }
+                        }
 
-                            index += 1;
+                        if self.local_player_handle.is_none() {
+                            self.local_player_handle = Some(index);
+                        } else {
+                            self.remote_player_handle = Some(index);
+                        }
+
+                        if peer_id == host_peer {
+                            host_handle = Some(index);
                         }
-                        _ => {}
                     }
                 }
 
-                let host_peer =
-                    PeerId(Uuid::parse_str(&spectate.to_spectate.clone().unwrap()).unwrap());
+                self.players.add(None, PlayerKind::Spectator, None);
+                self.players.add(
+                    host_handle,
+                    PlayerKind::Remote,
+                    Some(Address::Peer(host_peer)),
+                );
 
-                let sess = session.start_spectator_session(Address::Peer(host_peer), channel);
+                let sess = session.start_spectator_session(
+                    Address::Peer(host_peer),
+                    NetworkSimulator::new(
+                        PeerChannel::new(channel, self.packet_guard.clone()),
+                        config.network_simulation.clone(),
+                    ),
+                );
 
                 self.session = Some(SessionType::Spectate(sess));
                 self.is_spectator = true;
@@ -340,10 +859,23 @@ impl Netplay {
         }
 
         if let Some(server) = config.netplay.server_conf {
-            let (mut socket, future_msg) = WebRtcSocket::new_ggrs(server.room_url.unwrap());
+            let exchange_desync_states = self
+                .forensics
+                .as_ref()
+                .is_some_and(|forensics| forensics.should_exchange());
+
+            //The reliable channel is always there so the socket type doesn't depend on the config, it's only used for desync states
+            let (mut socket, future_msg) = WebRtcSocket::builder(server.room_url.unwrap())
+                .add_channel(ChannelConfig::unreliable())
+                .add_channel(ChannelConfig::reliable())
+                .build();
 
             let channel = socket.take_channel(0).unwrap();
 
+            if exchange_desync_states {
+                self.forensics_channel = socket.take_channel(1).ok();
+            }
+
             {
                 let mut stp = SHOULD_STOP_MATCHBOX_FUTURE.lock().unwrap();
                 *stp = false;
@@ -353,6 +885,8 @@ impl Netplay {
 
             let shared_players: Arc<Mutex<Vec<PlayerType<PeerId>>>> = Arc::new(Mutex::new(vec![]));
             let clone_for_thread = shared_players.clone();
+            let shared_id: Arc<Mutex<Option<PeerId>>> = Arc::new(Mutex::new(None));
+            let id_for_thread = shared_id.clone();
             let is_host = server.is_host;
 
             session = session.with_num_players(config.netplay.num_players as usize);
@@ -387,25 +921,23 @@ impl Netplay {
 
                         while !should_stop {
                             {
-                                match SHOULD_STOP_MATCHBOX_FUTURE.try_lock() {
-                                    Ok(stp) => {
-                                        should_stop = *stp;
-                                    }
-                                    Err(_) => {}
+                                if let Ok(stp) = SHOULD_STOP_MATCHBOX_FUTURE.try_lock() {
+                                    should_stop = *stp;
                                 }
                             }
 
                             socket.update_peers();
 
                             if !ignore_player_update {
-                                match clone_for_thread.lock() {
-                                    Ok(mut players) => {
-                                        *players = socket.players();
-                                    }
-                                    Err(_) => {}
+                                if let Ok(mut players) = clone_for_thread.lock() {
+                                    *players = socket_players(&socket);
                                 }
 
-                                ignore_player_update = socket.players().len() >= max_players
+                                if let Ok(mut id) = id_for_thread.lock() {
+                                    *id = socket.id();
+                                }
+
+                                ignore_player_update = socket_players(&socket).len() >= max_players
                             }
 
                             select! {
@@ -420,12 +952,9 @@ impl Netplay {
                                     let mut can_go = false;
 
                                         while !can_go {
-                                            match SHOULD_STOP_MATCHBOX_FUTURE.try_lock() {
-                                                Ok(mut stp) => {
-                                                    *stp = true;
-                                                    can_go = true;
-                                                }
-                                                Err(_) => {}
+                                            if let Ok(mut stp) = SHOULD_STOP_MATCHBOX_FUTURE.try_lock() {
+                                                *stp = true;
+                                                can_go = true;
                                             }
                                         }
 
@@ -457,19 +986,13 @@ impl Netplay {
                 }
 
                 {
-                    match SHOULD_STOP_MATCHBOX_FUTURE.try_lock() {
-                        Ok(stp) => {
-                            should_stop = *stp;
-                        }
-                        Err(_) => {}
+                    if let Ok(stp) = SHOULD_STOP_MATCHBOX_FUTURE.try_lock() {
+                        should_stop = *stp;
                     }
                 }
 
-                match clone.try_lock() {
-                    Ok(pl) => {
-                        players_connected = pl.clone();
-                    }
-                    Err(_) => {}
+                if let Ok(pl) = clone.try_lock() {
+                    players_connected = pl.clone();
                 }
 
                 if players_connected.len()
@@ -502,19 +1025,20 @@ impl Netplay {
                 == config.netplay.num_players as usize + spectator_from_config.len()
                 || players_connected.len() == config.netplay.num_players as usize
             {
-                //Add players
-                for (i, player) in players_connected_typed
-                    .clone()
+                let local_id = shared_id
+                    .lock()
+                    .unwrap()
+                    .ok_or("No id assigned by the signaling server".to_string())?;
+
+                //Add players, handles follow the peer ids so every peer agrees on them
+                for (i, player) in players_by_handle(&players_connected_typed, Some(local_id))
                     .into_iter()
-                    .filter(|p| match p {
-                        PlayerType::Spectator(_) => false,
-                        _ => true,
-                    })
                     .enumerate()
                 {
                     match player {
                         PlayerType::Local => {
                             self.local_player_handle = Some(i);
+                            self.players.add(Some(i), PlayerKind::Local, None);
                             session = session
                                 .add_player(PlayerType::Local, i)
                                 .expect("failed to add player");
@@ -531,6 +1055,11 @@ impl Netplay {
                             }
 
                             self.remote_player_handle = Some(i);
+                            self.players.add(
+                                Some(i),
+                                PlayerKind::Remote,
+                                Some(Address::Peer(peer_id)),
+                            );
                             session = session
                                 .add_player(PlayerType::Remote(Address::Peer(peer_id)), i)
                                 .expect("failed to add player");
@@ -539,35 +1068,82 @@ impl Netplay {
                     }
                 }
 
+                let mut socket = CompositeSocket::new().with_transport(
+                    Transport::Matchbox,
+                    PeerChannel::new(channel, self.packet_guard.clone()),
+                );
+
                 //Add spectators
                 if is_host {
-                    for (i, player) in players_connected_typed
-                        .clone()
-                        .into_iter()
-                        .filter(|p| match p {
-                            PlayerType::Spectator(_) => true,
-                            _ => false,
-                        })
-                        .enumerate()
-                    {
-                        match player {
-                            PlayerType::Spectator(peer_id) => {
-                                self.spectators_handles
-                                    .push(config.netplay.num_players as usize + i);
-                                session = session
-                                    .add_player(
-                                        PlayerType::Spectator(Address::Peer(peer_id)),
-                                        config.netplay.num_players as usize + i,
-                                    )
-                                    .expect("failed to add player");
-                            }
-                            _ => {}
+                    let peer_spectators = players_connected_typed
+                        .iter()
+                        .filter(|p| matches!(p, PlayerType::Spectator(_)))
+                        .count();
+
+                    if !udp_spectators.is_empty() {
+                        let udp_port = server
+                            .udp_port
+                            .ok_or("UdpPort is needed for direct UDP spectators".to_string())?;
+                        let udp_socket = DirectUdpSocket::bind_to_port(
+                            udp_port,
+                            self.packet_guard.clone(),
+                            None,
+                        )?;
+
+                        socket = socket.with_transport(Transport::Udp, udp_socket);
+                    }
+
+                    for (i, spectator) in udp_spectators.iter().enumerate() {
+                        let handle = config.netplay.num_players as usize + peer_spectators + i;
+                        let addr = spectator.parse::<SocketAddr>().unwrap();
+
+                        self.spectators_handles.push(handle);
+                        self.players.add(
+                            Some(handle),
+                            PlayerKind::Spectator,
+                            Some(Address::Socket(addr)),
+                        );
+                        session = session
+                            .add_player(PlayerType::Spectator(Address::Socket(addr)), handle)
+                            .map_err(|e| format!("Couldn't add spectator {} : {}", handle, e))?;
+                    }
+
+                    let mut peer_spectators_typed: Vec<PlayerType<PeerId>> =
+                        players_connected_typed
+                            .clone()
+                            .into_iter()
+                            .filter(|p| matches!(p, PlayerType::Spectator(_)))
+                            .collect();
+
+                    peer_spectators_typed.sort_by_key(|p| match p {
+                        PlayerType::Spectator(peer_id) => Some(peer_id.0),
+                        _ => None,
+                    });
+
+                    for (i, player) in peer_spectators_typed.into_iter().enumerate() {
+                        if let PlayerType::Spectator(peer_id) = player {
+                            self.spectators_handles
+                                .push(config.netplay.num_players as usize + i);
+                            self.players.add(
+                                Some(config.netplay.num_players as usize + i),
+                                PlayerKind::Spectator,
+                                Some(Address::Peer(peer_id)),
+                            );
+                            session = session
+                                .add_player(
+                                    PlayerType::Spectator(Address::Peer(peer_id)),
+                                    config.netplay.num_players as usize + i,
+                                )
+                                .expect("failed to add player");
                         }
                     }
                 }
 
                 let sess = session
-                    .start_p2p_session(channel)
+                    .start_p2p_session(NetworkSimulator::new(
+                        socket,
+                        config.network_simulation.clone(),
+                    ))
                     .expect("failed to start session");
 
                 info!("Starting p2p session");
@@ -580,57 +1156,121 @@ impl Netplay {
             return Err("Initialization failed".to_string());
         }
 
-        if let Some(local) = config.netplay.local_conf {
-            match local.remote_addr.parse::<SocketAddr>() {
-                Ok(socket) => {
-                    let remote_addr: SocketAddr = socket;
-                    let local_port = local.port;
-                    let socket = UdpNonBlockingSocket::bind_to_port(local_port).unwrap();
+        if let Some(local) = config.netplay.local_conf.clone() {
+            let cipher = local
+                .pre_shared_key
+                .as_ref()
+                .map(|key| PacketCipher::new(key, local.encrypt));
+
+            if cipher.is_none() && local.encrypt {
+                warn!("Encrypt needs a pre shared key, packets won't be protected");
+            }
+
+            //The launcher may have bound the socket already to share its port
+            let udp_socket = match self.prebound_socket.take() {
+                Some(udp_socket) => udp_socket,
+                None => DirectUdpSocket::bind(local.bind_addr.as_deref(), local.port)?,
+            };
+
+            let mut socket = DirectUdpSocket::new(udp_socket, self.packet_guard.clone(), cipher)?;
 
-                    if local.player_draw == 0 {
-                        self.local_player_handle = Some(0);
-                        self.remote_player_handle = Some(1);
-                    } else {
-                        self.local_player_handle = Some(1);
-                        self.remote_player_handle = Some(0);
+            self.local_port = socket.local_port();
+
+            let players = match (local.players.is_empty(), local.rendezvous.as_ref()) {
+                (true, Some(rendezvous)) => {
+                    let (remote_addr, player_draw) = rendezvous::connect(
+                        &mut socket,
+                        &rendezvous.server,
+                        &rendezvous.session,
+                        Duration::from_secs(RENDEZVOUS_TIMEOUT_SECS),
+                    )?;
+
+                    two_players(Address::Socket(remote_addr), player_draw)
+                }
+                (true, None) => {
+                    let remote_addr = match &local.announce {
+                        Some(announce) => self.wait_for_lan_peer(&config, &mut socket, announce)?,
+                        None => socket.resolve(&local.remote_addr)?,
+                    };
+
+                    if local.join_announced {
+                        rendezvous::join(
+                            &mut socket,
+                            remote_addr,
+                            Duration::from_secs(JOIN_TIMEOUT_SECS),
+                        )?;
                     }
 
-                    let session = SessionBuilder::<GGRSConfig>::new()
-                        .with_num_players(2)
-                        .add_player(PlayerType::Local, self.local_player_handle.unwrap())
-                        .unwrap()
-                        .add_player(
-                            PlayerType::Remote(Address::Socket(remote_addr)),
-                            self.remote_player_handle.unwrap(),
-                        )
-                        .unwrap()
-                        .with_input_delay(config.input_delay as usize)
-                        .with_disconnect_timeout(Duration::from_secs(5))
-                        .with_desync_detection_mode(DesyncDetection::On { interval: 500 })
-                        .start_p2p_session(socket)
-                        .unwrap();
-
-                    info!("Starting local p2p session");
-
-                    self.session = Some(SessionType::P2P(session));
-                    return Ok(());
+                    two_players(Address::Socket(remote_addr), local.player_draw)
                 }
-                Err(e) => return Err(format!("Can't parse remote addr : {}", e)),
-            }
+                (false, _) => {
+                    if local.players.len() != config.netplay.num_players as usize {
+                        return Err(format!(
+                            "{} players listed for a {} players session",
+                            local.players.len(),
+                            config.netplay.num_players
+                        ));
+                    }
+
+                    local
+                        .players
+                        .iter()
+                        .map(|player| match &player.addr {
+                            Some(addr) => socket
+                                .resolve(addr)
+                                .map(|addr| (player.handle, Some(Address::Socket(addr)))),
+                            None => Ok((player.handle, None)),
+                        })
+                        .collect::<Result<Vec<(usize, Option<Address>)>, String>>()?
+                }
+            };
+
+            let spectators = local
+                .spectators
+                .iter()
+                .map(|spectator| socket.resolve(spectator).map(Address::Socket))
+                .collect::<Result<Vec<Address>, String>>()?;
+
+            info!(
+                "Starting local p2p session with {} players and {} spectators",
+                players.len(),
+                spectators.len()
+            );
+
+            self.start_direct_session(&config, socket, players, spectators)?;
+
+            return Ok(());
         }
 
         if config.is_test() {
             info!("Starting test session");
 
+            let test = config.test.unwrap();
+            test.validate()?;
+
+            self.input_generators = test
+                .generated_handles()
+                .into_iter()
+                .map(|handle| {
+                    InputGenerator::new(&test.input_generator, handle).map(|g| (handle, g))
+                })
+                .collect::<Result<Vec<(usize, InputGenerator)>, String>>()?;
+
             let session: SyncTestSession<GGRSConfig> = SessionBuilder::new()
-                .with_num_players(2)
-                .with_check_distance(config.test.unwrap().check_distance as usize)
+                .with_num_players(test.num_players)
+                .with_check_distance(test.check_distance as usize)
                 .with_input_delay(config.input_delay as usize)
                 .start_synctest_session()
                 .unwrap();
 
-            self.local_player_handle = Some(0);
-            self.remote_player_handle = Some(1);
+            self.local_player_handle = Some(test.host_handles[0]);
+            self.remote_player_handle =
+                (0..test.num_players).find(|handle| *handle != test.host_handles[0]);
+            self.test_host_handles = test.host_handles;
+
+            for handle in 0..test.num_players {
+                self.players.add(Some(handle), PlayerKind::Local, None);
+            }
 
             self.session = Some(SessionType::Test(session));
             return Ok(());
@@ -639,6 +1279,37 @@ impl Netplay {
         Err("Not suitable configuration (Test, local or matchbox server) found".to_string())
     }
 
+    //Announces the session on the LAN until a peer joins it
+    fn wait_for_lan_peer(
+        &self,
+        config: &AppConfig,
+        socket: &mut DirectUdpSocket,
+        announce: &NetplayAnnounceConfig,
+    ) -> Result<SocketAddr, String> {
+        if config.netplay.num_players != 2 {
+            return Err("Only two players sessions can be announced".to_string());
+        }
+
+        let port = socket
+            .local_port()
+            .ok_or("Couldn't read the session port to announce")?;
+
+        let _announcer = discovery::Announcer::start(HostAnnouncement {
+            name: announce.name.clone(),
+            game_version: announce.game_version.clone(),
+            num_players: config.netplay.num_players as u32,
+            free_slots: config.netplay.num_players as u32 - 1,
+            port,
+            host_player_draw: config
+                .netplay
+                .local_conf
+                .as_ref()
+                .map_or(0, |local| local.player_draw),
+        })?;
+
+        rendezvous::wait_for_join(socket, Duration::from_secs(announce.wait_secs))
+    }
+
     pub fn poll_remote(&mut self) -> Result<(), String> {
         let session_res = self.session();
 
@@ -647,12 +1318,28 @@ impl Netplay {
 
             self.session = Some(session.retrieve());
 
+            self.receive_desync_states();
+            self.players.exchange(&self.packet_guard)?;
+
+            for warning in self.packet_guard.take_warnings() {
+                self.pending_events
+                    .push(Box::leak(warning.into_boxed_str()));
+            }
+
             Ok(())
         } else {
             Err("poll_remote: No session found".to_string())
         }
     }
 
+    pub fn players(&self) -> Vec<PlayerEntry> {
+        self.players.entries().to_vec()
+    }
+
+    pub fn set_connection_state(&mut self, addr: &Address, state: ConnectionState) {
+        self.players.set_state(addr, state);
+    }
+
     pub fn is_synchronized(&mut self) -> bool {
         let session_res = self.session();
 
@@ -661,23 +1348,48 @@ impl Netplay {
 
             self.session = Some(session.retrieve());
 
-            is_syncronized
+            //A peer with other session settings would desync, never start before every one agreed
+            is_syncronized && self.players.settings_agreed()
         } else {
             false
         }
     }
 
     pub fn advance_frame(&mut self, input: Input) -> Result<(), String> {
+        let inputs = vec![input; self.test_host_handles.len().max(1)];
+
+        self.advance_frame_with_host_inputs(inputs)
+    }
+
+    //One input per host handle of the test config, in the same order
+    pub fn advance_frame_with_inputs(&mut self, inputs: Vec<Input>) -> Result<(), String> {
+        if !self.is_test {
+            return Err("Inputs per handle are only taken by test sessions".to_string());
+        }
+
+        if inputs.len() != self.test_host_handles.len() {
+            return Err(format!(
+                "Got {} inputs for {} host handles",
+                inputs.len(),
+                self.test_host_handles.len()
+            ));
+        }
+
+        self.advance_frame_with_host_inputs(inputs)
+    }
+
+    fn advance_frame_with_host_inputs(&mut self, inputs: Vec<Input>) -> Result<(), String> {
+        let input = inputs[0];
         let session_res = self.session();
 
         if let Some(mut session) = session_res {
             if !self.is_spectator {
-                if let None = self.local_player_handle {
-                    return Err(format!("No local player handle"));
+                if self.local_player_handle.is_none() {
+                    return Err("No local player handle".to_string());
                 }
 
-                if let None = self.remote_player_handle {
-                    return Err(format!("No remote player handle"));
+                if self.remote_player_handle.is_none() {
+                    return Err("No remote player handle".to_string());
                 }
 
                 if let Err(e) = session.add_local_input(self.local_player_handle.unwrap(), input) {
@@ -685,29 +1397,32 @@ impl Netplay {
                 }
 
                 if self.is_test {
-                    let res: Result<(), GGRSError>;
-
-                    // let mut rng = rand::thread_rng();
-
-                    // let rand = rng.gen_range(0..10);
+                    let frame = self.game_state.frame();
 
-                    // if rand % 2 == 0 {
-                    if self.game_state.frame() % 120 > 60 {
-                        res = session
-                            .add_local_input(self.remote_player_handle.unwrap(), Input::default());
-                    } else {
-                        res = session
-                            .add_local_input(self.remote_player_handle.unwrap(), Input::default());
-                        //we don't care on test mode
+                    for (handle, input) in self.test_host_handles.iter().zip(inputs).skip(1) {
+                        if let Err(e) = session.add_local_input(*handle, input) {
+                            return Err(format!("Couldn't added test input : {}", e));
+                        }
                     }
 
-                    if let Err(e) = res {
-                        return Err(format!("Couldn't added test input : {}", e));
+                    for (handle, generator) in self.input_generators.iter_mut() {
+                        let generated = generator.next(frame, input);
+
+                        if let Err(e) = session.add_local_input(*handle, generated) {
+                            return Err(format!("Couldn't added test input : {}", e));
+                        }
                     }
                 }
             }
 
             if self.requests().is_empty() {
+                //Skipped by the host like a frame over the prediction threshold
+                if session.waits_for_checked_frame(DESYNC_DETECTION_INTERVAL) {
+                    self.session = Some(session.retrieve());
+
+                    return Err("PredictionThreshold".to_string());
+                }
+
                 match session.advance_frame() {
                     Ok(requests) => {
                         self.update_requests(requests);
@@ -721,10 +1436,21 @@ impl Netplay {
 
                         return Err("PredictionThreshold".to_string());
                     }
+                    Err(GGRSError::MismatchedChecksum { frame }) => {
+                        self.session = Some(session.retrieve());
+
+                        return Err(match self.synctest.as_mut() {
+                            Some(synctest) => {
+                                synctest.fail(frame);
+                                synctest.mismatch_message(frame)
+                            }
+                            None => format!("GGRSError : Checksum mismatch at frame {}", frame),
+                        });
+                    }
                     Err(e) => {
                         self.session = Some(session.retrieve());
 
-                        return Err(format!("GGRSError : {}", e.to_string()));
+                        return Err(format!("GGRSError : {}", e));
                     }
                 };
             } else {
@@ -743,14 +1469,136 @@ impl Netplay {
     pub fn events(&mut self) -> Vec<&'static str> {
         let session_res = self.session();
 
+        if let Some(lobby) = self.lobby.as_mut() {
+            self.pending_events.extend(lobby.update());
+        }
+
+        let mut events: Vec<&'static str> = self.pending_events.drain(..).collect();
+
         if let Some(mut session) = session_res {
-            let events: Vec<&'static str> = session.events(self);
+            events.extend(session.events(self));
 
             self.session = Some(session.retrieve());
+        }
 
-            events
-        } else {
-            vec![]
+        events
+    }
+
+    pub fn on_desync(
+        &mut self,
+        frame: i32,
+        local_checksum: u128,
+        remote_checksum: u128,
+        addr: &Address,
+    ) -> &'static str {
+        warn!("DesyncDetected from {addr} at frame {frame} , local checksum {local_checksum} , remote checksum {remote_checksum}");
+
+        let mut str = format!("DesyncDetected from {addr} at frame {frame} , local checksum {local_checksum} , remote checksum {remote_checksum}");
+        let mut exchange = false;
+
+        if let (Some(forensics), Some(config)) = (self.forensics.as_mut(), self.config.as_ref()) {
+            match forensics.dump(
+                frame,
+                local_checksum,
+                remote_checksum,
+                &addr.to_string(),
+                config,
+            ) {
+                Ok(path) => str.push_str(&format!(" , dump written to {}", path.display())),
+                Err(e) => error!("Couldn't write desync dump : {}", e),
+            }
+
+            exchange = forensics.should_exchange();
+        }
+
+        if exchange {
+            self.send_desync_state(frame, addr);
+        }
+
+        Box::leak(str.into_boxed_str())
+    }
+
+    //Matchbox peers get the state on its own reliable channel, direct ones in control packets that can get lost
+    fn send_desync_state(&mut self, frame: i32, addr: &Address) {
+        let forensics = match self.forensics.as_ref() {
+            Some(forensics) => forensics,
+            None => return,
+        };
+
+        let chunk_size = match addr {
+            Address::Peer(_) => CHUNK_SIZE,
+            Address::Socket(_) | Address::Loopback(_) => DIRECT_CHUNK_SIZE,
+        };
+
+        for chunk in forensics.chunks(frame, chunk_size) {
+            let packet = match bincode::serialize(&chunk) {
+                Ok(packet) => packet,
+                Err(e) => {
+                    warn!("Couldn't serialize desync state chunk : {}", e);
+                    continue;
+                }
+            };
+
+            match (addr, self.forensics_channel.as_mut()) {
+                (Address::Peer(peer_id), Some(channel)) => {
+                    channel.send(packet.into_boxed_slice(), *peer_id)
+                }
+                (Address::Peer(_), None) => {
+                    warn!("No desync state channel to reach {}", addr);
+                    return;
+                }
+                (Address::Socket(_) | Address::Loopback(_), _) => {
+                    self.packet_guard
+                        .post_control(addr, ControlKind::DesyncState, &packet)
+                }
+            }
+        }
+    }
+
+    fn receive_desync_states(&mut self) {
+        let mut packets = self.packet_guard.take_control(ControlKind::DesyncState);
+
+        if let Some(channel) = self.forensics_channel.as_mut() {
+            packets.extend(
+                channel
+                    .receive()
+                    .into_iter()
+                    .map(|(peer_id, packet)| (Address::Peer(peer_id), packet.into_vec())),
+            );
+        }
+
+        let forensics = match self.forensics.as_mut() {
+            Some(forensics) => forensics,
+            None => return,
+        };
+
+        for (address, packet) in packets {
+            let chunk: StateChunk = match bincode::deserialize(&packet) {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    warn!("Invalid desync state chunk from {} : {}", address, e);
+                    continue;
+                }
+            };
+
+            let frame = chunk.frame;
+
+            match forensics.receive_chunk(&address.to_string(), chunk) {
+                Some(Ok(report)) => {
+                    let str = format!(
+                        "DesyncDiff with {} at frame {} , first difference at {:?} , {} differing ranges , diverged sections : [{}]",
+                        address,
+                        frame,
+                        report.first_difference,
+                        report.ranges.len(),
+                        report.diverged_sections.join(", ")
+                    );
+                    let str: &'static str = Box::leak(str.into_boxed_str());
+                    self.pending_events.push(str);
+                }
+                Some(Err(e)) => warn!("Couldn't compare desync state with {} : {}", address, e),
+                None => {}
+            }
         }
     }
 
@@ -763,10 +1611,7 @@ impl Netplay {
     }
 
     pub fn requests(&self) -> Vec<NetplayRequest> {
-        self.requests
-            .iter()
-            .map(|req| NetplayRequest::new(req))
-            .collect()
+        self.requests.iter().map(NetplayRequest::new).collect()
     }
 
     pub fn update_requests(&mut self, requests: Vec<GGRSRequest<GGRSConfig>>) {
@@ -776,20 +1621,34 @@ impl Netplay {
     pub unsafe fn handle_save_game_state_request(
         &mut self,
         game_state: GameState,
+        checksum_source: ChecksumSource,
     ) -> Result<(), String> {
         if !self.requests.is_empty() {
             let req = self.requests.first().unwrap();
 
             return match req {
                 GGRSRequest::SaveGameState { cell, frame } => {
-                    assert_eq!(self.game_state.frame(), *frame);
+                    let frame = *frame;
 
-                    let buffer = bincode::serialize(&game_state.data()).unwrap();
-                    let checksum = fletcher16(&buffer) as u128;
-                    cell.save(*frame, Some(game_state.clone()), Some(checksum as u128));
+                    assert_eq!(self.game_state.frame(), frame);
 
-                    self.game_state = game_state.clone();
-                    self.game_state.update_frame(*frame);
+                    let checksum =
+                        checksum_of(self.checksum_algorithm, &game_state, &checksum_source)?;
+
+                    if let Some(forensics) = self.forensics.as_mut() {
+                        forensics.record_state(frame, &game_state);
+                    }
+
+                    if let Some(synctest) = self.synctest.as_mut() {
+                        synctest.record(frame, checksum, &game_state);
+                    }
+
+                    let stored = self.state_store.store(&game_state, frame)?;
+                    cell.save(frame, Some(stored), Some(checksum));
+
+                    //GGRS keeps the encoded state, the raw one stays readable here
+                    self.game_state = game_state;
+                    self.game_state.update_frame(frame);
 
                     self.requests.remove(0);
 
@@ -807,27 +1666,80 @@ impl Netplay {
         Err("Requests are empty".to_string())
     }
 
+    pub unsafe fn handle_save_game_state_sections_request(
+        &mut self,
+        sections: Vec<(String, Vec<u8>)>,
+    ) -> Result<(), String> {
+        let mut bytes = vec![];
+        let mut layout = vec![];
+
+        for (name, data) in sections {
+            if layout
+                .iter()
+                .any(|section: &StateSection| section.name == name)
+            {
+                return Err(format!("Duplicated game state section {}", name));
+            }
+
+            layout.push(StateSection {
+                checksum: checksum(self.checksum_algorithm, &data),
+                name,
+                offset: bytes.len(),
+                len: data.len(),
+            });
+
+            bytes.extend(data);
+        }
+
+        let mut game_state = GameState::encoded(bytes, StateEncoding::Raw);
+        game_state.set_sections(layout);
+
+        self.handle_save_game_state_request(game_state, ChecksumSource::Sections)
+    }
+
+    pub fn game_state_sections(&self) -> Vec<StateSection> {
+        self.game_state.sections().to_vec()
+    }
+
+    pub fn synctest_report(&self) -> Option<SyncTestMismatch> {
+        self.synctest
+            .as_ref()
+            .and_then(|synctest| synctest.report())
+    }
+
+    pub fn set_disconnected_input_callback(&mut self, callback: Option<DisconnectedInputCallback>) {
+        self.disconnected_input_callback = callback;
+    }
+
     pub fn handle_advance_frame_request(&mut self) -> Vec<Input> {
         if !self.requests.is_empty() {
             let req = self.requests.first().unwrap();
 
             return match req {
                 GGRSRequest::AdvanceFrame { inputs } => {
+                    let frame = self.game_state.frame();
+                    let statuses = inputs.clone();
+
                     self.game_state.add_frame();
 
-                    let inputs: Vec<Input> = inputs
-                        .iter()
-                        .map(|(input, status)| {
-                            return match *status {
-                                InputStatus::Confirmed => *input,
-                                InputStatus::Predicted => *input,
-                                InputStatus::Disconnected => Input::default(),
-                            };
+                    let inputs: Vec<Input> = statuses
+                        .into_iter()
+                        .enumerate()
+                        .map(|(handle, (input, status))| match status {
+                            InputStatus::Confirmed | InputStatus::Predicted => {
+                                self.record_input(handle, frame, input);
+                                input
+                            }
+                            InputStatus::Disconnected => self.disconnected_input(handle, frame),
                         })
                         .collect();
 
                     self.requests.remove(0);
 
+                    if let Some(forensics) = self.forensics.as_mut() {
+                        forensics.record_inputs(frame, &inputs);
+                    }
+
                     self.current_inputs = Some(inputs.clone());
 
                     inputs
@@ -839,6 +1751,51 @@ impl Netplay {
         vec![]
     }
 
+    //Predictions are recorded too: a frame GGRS confirms without rolling back was predicted right,
+    //the other ones are simulated again with the confirmed input and overwrite it
+    fn record_input(&mut self, handle: usize, frame: i32, input: Input) {
+        let history = self.confirmed_inputs.entry(handle).or_default();
+
+        history.insert(frame, input);
+
+        //Keep at least one input older than the depth, a disconnected player may need it
+        let horizon = frame - CONFIRMED_INPUTS_DEPTH;
+        if let Some(&oldest_needed) = history.range(..=horizon).next_back().map(|(f, _)| f) {
+            *history = history.split_off(&oldest_needed);
+        }
+    }
+
+    //GGRS disconnects a player at the frame every peer received its input for, inputs recorded
+    //past it before the peers agreed are dropped by using the first disconnected frame
+    fn last_confirmed_input(&mut self, handle: usize, frame: i32) -> Input {
+        let disconnected_at = self.disconnected_at.entry(handle).or_insert(frame);
+        *disconnected_at = (*disconnected_at).min(frame);
+
+        let disconnected_at = *disconnected_at;
+
+        self.confirmed_inputs
+            .get(&handle)
+            .and_then(|history| history.range(..disconnected_at).next_back())
+            .map(|(_, input)| *input)
+            .unwrap_or_default()
+    }
+
+    fn disconnected_input(&mut self, handle: usize, frame: i32) -> Input {
+        match self.disconnected_input_policy {
+            DisconnectedInputPolicy::Neutral => Input::default(),
+            DisconnectedInputPolicy::RepeatLastConfirmed => {
+                self.last_confirmed_input(handle, frame)
+            }
+            DisconnectedInputPolicy::Host => match self.disconnected_input_callback {
+                Some(callback) => {
+                    let last_confirmed = self.last_confirmed_input(handle, frame);
+                    callback(handle as i32, frame, last_confirmed)
+                }
+                None => Input::default(),
+            },
+        }
+    }
+
     pub unsafe fn handle_load_game_state_request(&mut self) -> Result<SafeBytes, String> {
         if !self.requests.is_empty() {
             let req = self.requests.first().unwrap();
@@ -848,13 +1805,15 @@ impl Netplay {
                     let to_load: GameState = cell
                         .load()
                         .expect("No data found when trying to load game state");
-                    self.game_state = to_load;
+                    let restored = self.state_store.restore(&to_load)?;
+                    let bytes = restored.data().to_safe_bytes();
 
+                    self.game_state = restored;
                     self.game_state.update_frame(*frame);
 
                     self.requests.remove(0);
 
-                    Ok(self.game_state.clone().data().to_safe_bytes())
+                    Ok(bytes)
                 }
                 _ => {
                     let err = format!(
@@ -901,6 +1860,16 @@ impl Netplay {
         }
     }
 
+    pub unsafe fn state_stats(&self, state_stats: *mut StateStats) -> Result<(), String> {
+        if state_stats.is_null() {
+            return Err("state_stats : null pointer".to_string());
+        }
+
+        (*state_stats) = self.state_store.stats();
+
+        Ok(())
+    }
+
     pub fn frames_ahead(&mut self) -> Result<i32, String> {
         let session_res = self.session();
 
@@ -916,14 +1885,96 @@ impl Netplay {
     }
 }
 
-fn fletcher16(data: &[u8]) -> u16 {
-    let mut sum1: u16 = 0;
-    let mut sum2: u16 = 0;
+//Matchbox and direct p2p sessions share it, a peer behaves the same under lag whatever the transport
+fn p2p_session_builder(config: &AppConfig) -> SessionBuilder<GGRSConfig> {
+    SessionBuilder::<GGRSConfig>::new()
+        .with_input_delay(config.input_delay as usize)
+        .with_max_prediction_window(MAX_PREDICTION_WINDOW)
+        .with_desync_detection_mode(DesyncDetection::On {
+            interval: DESYNC_DETECTION_INTERVAL,
+        })
+        .with_disconnect_timeout(Duration::from_secs(DISCONNECT_TIMEOUT_SECS))
+        .with_max_frames_behind(MAX_FRAMES_BEHIND)
+        .unwrap()
+        .with_catchup_speed(CATCHUP_SPEED)
+        .unwrap()
+}
+
+//WebRtcSocket::players only exists for single channel sockets
+fn socket_players(socket: &WebRtcSocket<MultipleChannels>) -> Vec<PlayerType<PeerId>> {
+    let local_id = match socket.id() {
+        Some(id) => id,
+        None => return vec![PlayerType::Local],
+    };
+
+    let mut ids: Vec<PeerId> = socket
+        .connected_peers()
+        .chain(std::iter::once(local_id))
+        .collect();
+    ids.sort();
+
+    ids.into_iter()
+        .map(|id| match id == local_id {
+            true => PlayerType::Local,
+            false => PlayerType::Remote(id),
+        })
+        .collect()
+}
 
-    for index in 0..data.len() {
-        sum1 = (sum1 + data[index] as u16) % 255;
-        sum2 = (sum2 + sum1) % 255;
+//Players sorted by peer id, the local one by its own id when given and left out otherwise
+fn players_by_handle(
+    players: &[PlayerType<PeerId>],
+    local_id: Option<PeerId>,
+) -> Vec<PlayerType<PeerId>> {
+    let mut sorted: Vec<(PeerId, PlayerType<PeerId>)> = players
+        .iter()
+        .filter_map(|player| match (player, local_id) {
+            (PlayerType::Local, Some(local_id)) => Some((local_id, PlayerType::Local)),
+            (PlayerType::Remote(peer_id), _) => Some((*peer_id, PlayerType::Remote(*peer_id))),
+            _ => None,
+        })
+        .collect();
+
+    sorted.sort_by_key(|(peer_id, _)| peer_id.0);
+
+    sorted.into_iter().map(|(_, player)| player).collect()
+}
+
+//Legacy two players layout, the player draw tells which handle is local
+fn two_players(remote_addr: Address, player_draw: u32) -> Vec<(usize, Option<Address>)> {
+    match player_draw {
+        0 => vec![(0, None), (1, Some(remote_addr))],
+        _ => vec![(0, Some(remote_addr)), (1, None)],
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
 
-    (sum2 << 8) | sum1
+    fn peer(id: u128) -> PeerId {
+        PeerId(Uuid::from_u128(id))
+    }
+
+    #[test]
+    fn players_by_handle_ignores_the_socket_order() {
+        let local_id = peer(2);
+        let players = vec![
+            PlayerType::Remote(peer(3)),
+            PlayerType::Local,
+            PlayerType::Remote(peer(1)),
+        ];
+        let mut swapped = players.clone();
+        swapped.reverse();
+
+        let expected = vec![
+            PlayerType::Remote(peer(1)),
+            PlayerType::Local,
+            PlayerType::Remote(peer(3)),
+        ];
+
+        assert_eq!(players_by_handle(&players, Some(local_id)), expected);
+        assert_eq!(players_by_handle(&swapped, Some(local_id)), expected);
+    }
 }
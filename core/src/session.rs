@@ -1,13 +1,18 @@
-use tracing::{info, warn};
+use tracing::{error, info, warn};
 
 use ggrs::{
     GGRSError, GGRSEvent, GGRSRequest, NetworkStats, P2PSession, SpectatorSession, SyncTestSession,
 };
 
 use crate::{
-    config::ggrs_config::GGRSConfig, model::input::Input, neplay::Netplay, set_netplay_disconnected,
+    config::ggrs_config::GGRSConfig,
+    core::checksum,
+    model::{input::Input, player_info::ConnectionState},
+    neplay::Netplay,
+    set_netplay_disconnected,
 };
 
+#[allow(clippy::large_enum_variant)]
 pub enum SessionType {
     P2P(P2PSession<GGRSConfig>),
     Test(SyncTestSession<GGRSConfig>),
@@ -20,6 +25,7 @@ pub trait Session<Config: ggrs::Config> {
     fn is_synchronized(&self) -> bool;
     fn add_local_input(&mut self, player_handle: usize, input: Input) -> Result<(), GGRSError>;
     fn advance_frame(&mut self) -> Result<Vec<GGRSRequest<Config>>, GGRSError>;
+    fn waits_for_checked_frame(&mut self, interval: u32) -> bool;
     fn net_stats(&mut self, remote_player_handle: usize) -> Result<NetworkStats, GGRSError>;
     fn get_frames_ahead(&mut self) -> i32;
     fn retrieve(self: Box<Self>) -> SessionType;
@@ -35,6 +41,8 @@ impl Session<GGRSConfig> for P2PSession<GGRSConfig> {
 
             match event {
                 GGRSEvent::Synchronizing { addr, total, count } => {
+                    netplay.set_connection_state(&addr, ConnectionState::Connecting);
+
                     let str = format!(
                         "Synchronizing with {} total {} count {}",
                         addr, total, count
@@ -44,11 +52,15 @@ impl Session<GGRSConfig> for P2PSession<GGRSConfig> {
                     events.push(str)
                 }
                 GGRSEvent::Synchronized { addr } => {
+                    netplay.set_connection_state(&addr, ConnectionState::Connected);
+
                     let str = format!("Synchronized with {addr}");
                     let str: &'static str = Box::leak(str.into_boxed_str());
                     events.push(str)
                 }
                 GGRSEvent::Disconnected { addr } => {
+                    netplay.set_connection_state(&addr, ConnectionState::Disconnected);
+
                     if !netplay.is_a_remote_player(addr.clone()) {
                         continue;
                     }
@@ -64,6 +76,8 @@ impl Session<GGRSConfig> for P2PSession<GGRSConfig> {
                     addr,
                     disconnect_timeout,
                 } => {
+                    netplay.set_connection_state(&addr, ConnectionState::Interrupted);
+
                     if !netplay.is_a_remote_player(addr.clone()) {
                         continue;
                     }
@@ -83,6 +97,8 @@ impl Session<GGRSConfig> for P2PSession<GGRSConfig> {
                 }
 
                 GGRSEvent::NetworkResumed { addr } => {
+                    netplay.set_connection_state(&addr, ConnectionState::Connected);
+
                     if !netplay.is_a_remote_player(addr.clone()) {
                         continue;
                     }
@@ -92,14 +108,30 @@ impl Session<GGRSConfig> for P2PSession<GGRSConfig> {
                     events.push(str)
                 }
                 GGRSEvent::DesyncDetected {
-                    frame: _,
-                    local_checksum: _,
-                    remote_checksum: _,
-                    addr: _,
+                    frame,
+                    local_checksum,
+                    remote_checksum,
+                    addr,
                 } => {
-                    // let str = format!("DesyncDetected from {addr} at frame {frame} , local checksum {local_checksum} , remote checksum {remote_checksum}");
-                    // let str: &'static str = Box::leak(str.into_boxed_str());
-                    // events.push(str)
+                    let local_algorithm = checksum::algorithm_of(local_checksum);
+                    let remote_algorithm = checksum::algorithm_of(remote_checksum);
+
+                    if local_algorithm != remote_algorithm {
+                        error!(
+                            "Checksum algorithm mismatch with {}, local {:?} remote {:?}",
+                            addr, local_algorithm, remote_algorithm
+                        );
+
+                        let str = format!(
+                            "ChecksumAlgorithmMismatch with {}, local {:?} remote {:?}",
+                            addr, local_algorithm, remote_algorithm
+                        );
+                        let str: &'static str = Box::leak(str.into_boxed_str());
+                        events.push(str);
+                        continue;
+                    }
+
+                    events.push(netplay.on_desync(frame, local_checksum, remote_checksum, &addr));
                 }
             }
         }
@@ -123,6 +155,16 @@ impl Session<GGRSConfig> for P2PSession<GGRSConfig> {
         self.advance_frame()
     }
 
+    //GGRS 0.9 sends the checksum of the frame before each interval multiple as it was last saved,
+    //even as a prediction. That frame is only saved once its inputs are confirmed
+    fn waits_for_checked_frame(&mut self, interval: u32) -> bool {
+        let current = self.current_frame();
+
+        self.poll_remote_clients();
+
+        (current + 1) % interval as i32 == 0 && self.confirmed_frame() < current - 1
+    }
+
     fn net_stats(&mut self, remote_player_handle: usize) -> Result<NetworkStats, GGRSError> {
         self.network_stats(remote_player_handle)
     }
@@ -166,6 +208,10 @@ impl Session<GGRSConfig> for SyncTestSession<GGRSConfig> {
         self.advance_frame()
     }
 
+    fn waits_for_checked_frame(&mut self, _interval: u32) -> bool {
+        false
+    }
+
     fn net_stats(&mut self, _remote_player_handle: usize) -> Result<NetworkStats, GGRSError> {
         Ok(NetworkStats::new())
     }
@@ -184,12 +230,14 @@ impl Session<GGRSConfig> for SyncTestSession<GGRSConfig> {
 }
 
 impl Session<GGRSConfig> for SpectatorSession<GGRSConfig> {
-    fn events(&mut self, _netplay: &mut Netplay) -> Vec<&'static str> {
+    fn events(&mut self, netplay: &mut Netplay) -> Vec<&'static str> {
         let mut events: Vec<&'static str> = vec![];
 
-        for (_, event) in (self).events().enumerate() {
+        for event in self.events() {
             match event {
                 GGRSEvent::Synchronizing { addr, total, count } => {
+                    netplay.set_connection_state(&addr, ConnectionState::Connecting);
+
                     let str = format!(
                         "Synchronizing with {} total {} count {}",
                         addr, total, count
@@ -201,6 +249,8 @@ impl Session<GGRSConfig> for SpectatorSession<GGRSConfig> {
                     events.push(str)
                 }
                 GGRSEvent::Synchronized { addr } => {
+                    netplay.set_connection_state(&addr, ConnectionState::Connected);
+
                     let str = format!("Synchronized with {addr}");
                     let str: &'static str = Box::leak(str.into_boxed_str());
 
@@ -209,6 +259,8 @@ impl Session<GGRSConfig> for SpectatorSession<GGRSConfig> {
                     events.push(str)
                 }
                 GGRSEvent::Disconnected { addr } => {
+                    netplay.set_connection_state(&addr, ConnectionState::Disconnected);
+
                     set_netplay_disconnected(true);
                     let str = format!("Disconnected from {addr}");
                     let str: &'static str = Box::leak(str.into_boxed_str());
@@ -222,6 +274,8 @@ impl Session<GGRSConfig> for SpectatorSession<GGRSConfig> {
                     addr,
                     disconnect_timeout,
                 } => {
+                    netplay.set_connection_state(&addr, ConnectionState::Interrupted);
+
                     let str = format!(
                         "NetworkInterrupted with {}, will disconnect in {} ms",
                         addr, disconnect_timeout
@@ -243,6 +297,8 @@ impl Session<GGRSConfig> for SpectatorSession<GGRSConfig> {
                 }
 
                 GGRSEvent::NetworkResumed { addr } => {
+                    netplay.set_connection_state(&addr, ConnectionState::Connected);
+
                     let str = format!("NetworkResumed with {}", addr);
                     let str: &'static str = Box::leak(str.into_boxed_str());
 
@@ -274,6 +330,10 @@ impl Session<GGRSConfig> for SpectatorSession<GGRSConfig> {
         self.advance_frame()
     }
 
+    fn waits_for_checked_frame(&mut self, _interval: u32) -> bool {
+        false
+    }
+
     fn is_synchronized(&self) -> bool {
         self.current_state() == ggrs::SessionState::Running
     }
@@ -0,0 +1,372 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use ggrs_ffi::{
+    config::{
+        app_config::{AppConfig, ChecksumAlgorithm, DesyncDumpConfig},
+        ggrs_config::LoopbackNetwork,
+    },
+    model::{input::Input, netplay_request::NetplayRequest},
+    neplay::Netplay,
+};
+use rand::{rngs::StdRng, SeedableRng};
+
+const FRAMES: i32 = 1100; //Past two desync detection intervals (500), so checksums are compared
+const CHECKED_FRAME: i32 = 499; //GGRS compares the frame before the first interval
+const SETTLE_FRAMES: i32 = 20; //More than the prediction window, every compared frame is confirmed
+const TIMEOUT: Duration = Duration::from_secs(120);
+//Real frame pace, faster ticks leave more unacked inputs than GGRS fits in one packet under lag
+const FRAME_DURATION: Duration = Duration::from_millis(16);
+
+//Every byte of every input changes the state, so any wrong input or bad rollback shows up
+#[derive(Clone, Debug, PartialEq)]
+struct ToyGame {
+    frame: i32,
+    players: [u64; 2],
+}
+
+impl ToyGame {
+    fn new() -> Self {
+        Self {
+            frame: 0,
+            players: [1, 2],
+        }
+    }
+
+    fn advance(&mut self, inputs: &[Input]) {
+        for (player, input) in self.players.iter_mut().zip(inputs) {
+            for byte in bytemuck::bytes_of(input) {
+                *player = player.wrapping_mul(1_099_511_628_211) ^ *byte as u64;
+            }
+
+            *player = player.rotate_left(self.frame as u32 % 64);
+        }
+
+        self.frame += 1;
+    }
+
+    //Laid out like to_bytes once concatenated
+    fn sections(&self) -> Vec<(String, Vec<u8>)> {
+        let mut sections = vec![("frame".to_string(), self.frame.to_le_bytes().to_vec())];
+
+        for (index, player) in self.players.iter().enumerate() {
+            sections.push((format!("player_{}", index), player.to_le_bytes().to_vec()));
+        }
+
+        sections
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.sections()
+            .into_iter()
+            .flat_map(|(_, bytes)| bytes)
+            .collect()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let word =
+            |offset: usize| u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+
+        Self {
+            frame: i32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+            players: [word(4), word(12)],
+        }
+    }
+}
+
+struct Peer {
+    netplay: Netplay,
+    game: ToyGame,
+    rng: StdRng,
+    history: BTreeMap<i32, ToyGame>,
+    events: Vec<String>,
+    corrupt_from: Option<i32>, //Frame from which this peer simulates wrong, to force a desync
+}
+
+impl Peer {
+    fn new(netplay: Netplay, seed: u64) -> Self {
+        Self {
+            netplay,
+            game: ToyGame::new(),
+            rng: StdRng::seed_from_u64(seed),
+            history: BTreeMap::new(),
+            events: vec![],
+            corrupt_from: None,
+        }
+    }
+
+    fn tick(&mut self) {
+        self.netplay.poll_remote().unwrap();
+        self.events
+            .extend(self.netplay.events().iter().map(|event| event.to_string()));
+
+        if !self.netplay.is_synchronized() {
+            return;
+        }
+
+        match self.netplay.advance_frame(Input::random(&mut self.rng)) {
+            Ok(()) => self.handle_requests(),
+            Err(e) if e == "PredictionThreshold" => {}
+            Err(e) => panic!("Couldn't advance frame : {}", e),
+        }
+    }
+
+    fn handle_requests(&mut self) {
+        for request in self.netplay.requests() {
+            match request {
+                NetplayRequest::SaveGameState => unsafe {
+                    self.netplay
+                        .handle_save_game_state_sections_request(self.game.sections())
+                        .unwrap();
+                },
+                NetplayRequest::LoadGameState => unsafe {
+                    let bytes = self.netplay.handle_load_game_state_request().unwrap();
+
+                    self.game = ToyGame::from_bytes(bytes.slice());
+                    bytes.free();
+                },
+                NetplayRequest::AdvanceFrame => {
+                    let inputs = self.netplay.handle_advance_frame_request();
+
+                    self.game.advance(&inputs);
+
+                    if self
+                        .corrupt_from
+                        .is_some_and(|frame| self.game.frame >= frame)
+                    {
+                        self.game.players[1] ^= 1;
+                    }
+
+                    self.history.insert(self.game.frame, self.game.clone());
+                }
+            }
+        }
+    }
+}
+
+fn config(network_simulation: &str) -> AppConfig {
+    let json = format!(
+        r#"{{
+            "InputDelay": 2,
+            "Name": "rollback",
+            "Netplay": {{ "NumPlayers": 2 }},
+            "NetworkSimulation": {}
+        }}"#,
+        network_simulation
+    );
+
+    serde_json::from_str(&json).unwrap()
+}
+
+//Frames of the DesyncDetected events
+fn desync_frames(events: &[String]) -> Vec<i32> {
+    events
+        .iter()
+        .filter_map(|event| event.strip_prefix("DesyncDetected"))
+        .filter_map(|event| event.split(" at frame ").nth(1))
+        .filter_map(|event| event.split(' ').next()?.parse().ok())
+        .collect()
+}
+
+fn run_session(network_simulation: &str) {
+    let network = LoopbackNetwork::new();
+    let (socket_a, socket_b) = (network.socket(), network.socket());
+    let (address_a, address_b) = (socket_a.address(), socket_b.address());
+
+    let mut netplay_a = Netplay::new(None);
+    let mut netplay_b = Netplay::new(None);
+
+    netplay_a
+        .init_with_loopback(config(network_simulation), socket_a, address_b, 0)
+        .unwrap();
+    netplay_b
+        .init_with_loopback(config(network_simulation), socket_b, address_a, 1)
+        .unwrap();
+
+    let mut peers = [Peer::new(netplay_a, 1), Peer::new(netplay_b, 2)];
+    let start = Instant::now();
+
+    while peers
+        .iter()
+        .any(|peer| peer.game.frame < FRAMES + SETTLE_FRAMES)
+    {
+        assert!(
+            start.elapsed() < TIMEOUT,
+            "Peers stuck at frames {} and {}",
+            peers[0].game.frame,
+            peers[1].game.frame
+        );
+
+        for peer in peers.iter_mut() {
+            peer.tick();
+        }
+
+        sleep(FRAME_DURATION);
+    }
+
+    for frame in 1..=FRAMES {
+        assert_eq!(
+            peers[0].history.get(&frame),
+            peers[1].history.get(&frame),
+            "States differ at frame {}",
+            frame
+        );
+    }
+
+    //Only confirmed states are compared, a desync must come with states that really differ
+    for peer in peers.iter() {
+        for frame in desync_frames(&peer.events) {
+            assert_ne!(
+                peers[0].history.get(&frame),
+                peers[1].history.get(&frame),
+                "Desync reported at frame {} with identical states",
+                frame
+            );
+        }
+    }
+}
+
+#[test]
+fn peers_agree_without_lag() {
+    run_session("null");
+}
+
+#[test]
+fn peers_agree_under_simulated_lag() {
+    run_session(
+        r#"{ "LatencyMs": 30, "JitterMs": 15, "Loss": 0.05, "Duplication": 0.02, "Reordering": 0.05, "Seed": 7 }"#,
+    );
+}
+
+#[test]
+fn peers_with_other_checksum_algorithms_never_synchronize() {
+    let network = LoopbackNetwork::new();
+    let (socket_a, socket_b) = (network.socket(), network.socket());
+    let (address_a, address_b) = (socket_a.address(), socket_b.address());
+
+    let mut config_b = config("null");
+    config_b.checksum = ChecksumAlgorithm::Xxh3_64;
+
+    let mut netplay_a = Netplay::new(None);
+    let mut netplay_b = Netplay::new(None);
+
+    netplay_a
+        .init_with_loopback(config("null"), socket_a, address_b, 0)
+        .unwrap();
+    netplay_b
+        .init_with_loopback(config_b, socket_b, address_a, 1)
+        .unwrap();
+
+    let mut errors = [None, None];
+    let start = Instant::now();
+
+    while errors.iter().any(|error| error.is_none()) {
+        assert!(start.elapsed() < TIMEOUT, "No mismatch reported");
+
+        for (netplay, error) in [&mut netplay_a, &mut netplay_b]
+            .into_iter()
+            .zip(&mut errors)
+        {
+            if let Err(e) = netplay.poll_remote() {
+                *error = Some(e);
+            }
+
+            assert!(!netplay.is_synchronized());
+        }
+
+        sleep(FRAME_DURATION);
+    }
+
+    for error in errors.into_iter().flatten() {
+        assert!(error.starts_with("ChecksumAlgorithmMismatch"), "{}", error);
+    }
+}
+
+#[test]
+fn desync_is_dumped_and_diffed_on_both_peers() {
+    let network = LoopbackNetwork::new();
+    let sockets = [network.socket(), network.socket()];
+    let addresses = [sockets[0].address(), sockets[1].address()];
+    let directory = std::env::temp_dir().join(format!("ggrs_desync_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&directory);
+
+    let mut peers = vec![];
+
+    for (index, socket) in sockets.into_iter().enumerate() {
+        let mut config = config("null");
+        config.desync_dump = Some(DesyncDumpConfig {
+            directory: directory.join(index.to_string()).display().to_string(),
+            exchange: true,
+        });
+
+        let mut netplay = Netplay::new(None);
+        netplay
+            .init_with_loopback(config, socket, addresses[1 - index].clone(), index as u32)
+            .unwrap();
+
+        peers.push(Peer::new(netplay, index as u64 + 1));
+    }
+
+    peers[1].corrupt_from = Some(CHECKED_FRAME - 100);
+
+    let start = Instant::now();
+
+    while peers.iter().any(|peer| {
+        !peer
+            .events
+            .iter()
+            .any(|event| event.starts_with("DesyncDiff"))
+    }) {
+        assert!(start.elapsed() < TIMEOUT, "No desync diff reported");
+
+        for peer in peers.iter_mut() {
+            peer.tick();
+        }
+
+        sleep(FRAME_DURATION);
+    }
+
+    for (index, peer) in peers.iter().enumerate() {
+        let other = &peers[1 - index];
+        let dump = directory
+            .join(index.to_string())
+            .join(format!("desync_{}", CHECKED_FRAME));
+        let info: serde_json::Value =
+            serde_json::from_slice(&fs::read(dump.join("desync.json")).unwrap()).unwrap();
+        let remote_name = addresses[1 - index].to_string().replace(':', "_");
+
+        assert!(desync_frames(&peer.events).contains(&CHECKED_FRAME));
+        assert_ne!(
+            peer.history.get(&CHECKED_FRAME),
+            other.history.get(&CHECKED_FRAME)
+        );
+        assert_eq!(info["StateFound"], true);
+        assert_eq!(info["Sections"].as_array().unwrap().len(), 3);
+        assert_eq!(
+            fs::read(dump.join("local_state.bin")).unwrap(),
+            peer.history[&CHECKED_FRAME].to_bytes()
+        );
+        assert_eq!(
+            fs::read(dump.join(format!("remote_state_{}.bin", remote_name))).unwrap(),
+            other.history[&CHECKED_FRAME].to_bytes()
+        );
+        assert!(dump.join(format!("diff_{}.json", remote_name)).exists());
+
+        let diff = peer
+            .events
+            .iter()
+            .find(|event| event.starts_with("DesyncDiff"))
+            .unwrap();
+
+        assert!(
+            diff.contains(&format!("at frame {}", CHECKED_FRAME)),
+            "{}",
+            diff
+        );
+        assert!(diff.ends_with("diverged sections : [player_1]"), "{}", diff);
+    }
+
+    let _ = fs::remove_dir_all(&directory);
+}
@@ -39,8 +39,7 @@ pub fn catch_status(_attr: TokenStream, item: TokenStream) -> TokenStream {
         }
     };
 
-    let output = TokenStream::from(output);
-    output
+    TokenStream::from(output)
 }
 
 #[proc_macro_attribute]
@@ -79,6 +78,5 @@ pub fn catch_action_result(_attr: TokenStream, item: TokenStream) -> TokenStream
         }
     };
 
-    let output = TokenStream::from(output);
-    output
+    TokenStream::from(output)
 }
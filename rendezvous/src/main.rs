@@ -0,0 +1,99 @@
+//Rendezvous server for direct UDP sessions : pairs the two peers registering with the same session name
+//Usage : rendezvous [port]
+use std::collections::HashMap;
+use std::env;
+use std::net::SocketAddr;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use ggrs_ffi::{
+    config::ggrs_config::{DirectUdpSocket, PacketGuard},
+    core::rendezvous::{decode, player_draw, send, RendezvousMessage},
+};
+use tracing::{error, info};
+
+const DEFAULT_PORT: u16 = 7000;
+const REGISTRATION_TIMEOUT: Duration = Duration::from_secs(60);
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+fn main() {
+    tracing_subscriber::fmt()
+        .compact()
+        .with_target(false)
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    let port = match env::args().nth(1).map(|port| port.parse::<u16>()) {
+        Some(Ok(port)) => port,
+        Some(Err(e)) => {
+            error!("Invalid port : {}", e);
+            std::process::exit(1);
+        }
+        None => DEFAULT_PORT,
+    };
+
+    let mut socket = match DirectUdpSocket::bind(None, port)
+        .and_then(|socket| DirectUdpSocket::new(socket, PacketGuard::default(), None))
+    {
+        Ok(socket) => socket,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    info!("Rendezvous server listening on port {}", port);
+
+    let mut sessions: HashMap<String, Vec<(SocketAddr, Instant)>> = HashMap::new();
+
+    loop {
+        for (from, packet) in socket.receive_raw() {
+            let session = match decode(&packet) {
+                Some(RendezvousMessage::Register { session }) => session,
+                _ => continue,
+            };
+
+            let peers = sessions.entry(session.clone()).or_default();
+            peers.retain(|(addr, registered)| {
+                *addr != from && registered.elapsed() < REGISTRATION_TIMEOUT
+            });
+            peers.push((from, Instant::now()));
+
+            //Only the two latest registrations are paired
+            if peers.len() > 2 {
+                peers.remove(0);
+            }
+
+            match peers.as_slice() {
+                [(first, _), (second, _)] => {
+                    info!("Pairing {} and {} for {}", first, second, session);
+
+                    send(
+                        &socket,
+                        &RendezvousMessage::Peer {
+                            endpoint: *second,
+                            player_draw: player_draw(first, second),
+                        },
+                        first,
+                    );
+                    send(
+                        &socket,
+                        &RendezvousMessage::Peer {
+                            endpoint: *first,
+                            player_draw: player_draw(second, first),
+                        },
+                        second,
+                    );
+                }
+                _ => send(&socket, &RendezvousMessage::Registered, &from),
+            }
+        }
+
+        sessions.retain(|_, peers| {
+            peers.retain(|(_, registered)| registered.elapsed() < REGISTRATION_TIMEOUT);
+            !peers.is_empty()
+        });
+
+        sleep(POLL_INTERVAL);
+    }
+}
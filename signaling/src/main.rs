@@ -0,0 +1,230 @@
+//Matchbox compatible signaling server : rooms, next=N matchmaking and spectator slots
+//Usage : signaling [addr:port] [--spectator-slots <n>]
+//Players join ws://host/<room>?next=N, spectators add &spectator=true to watch the latest group of that room
+mod rooms;
+
+use std::{
+    collections::HashMap,
+    env,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, Query, State,
+    },
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use rooms::{RoomKey, Rooms};
+
+const DEFAULT_ADDR: &str = "0.0.0.0:3536";
+const DEFAULT_SPECTATOR_SLOTS: usize = 4;
+
+//Same messages as matchbox_socket, peer ids are plain uuids
+#[derive(Debug, Serialize, Deserialize)]
+enum PeerRequest {
+    Signal { receiver: Uuid, data: Value },
+    KeepAlive,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum PeerEvent {
+    IdAssigned(Uuid),
+    NewPeer(Uuid),
+    PeerLeft(Uuid),
+    Signal { sender: Uuid, data: Value },
+}
+
+#[derive(Debug, Deserialize)]
+struct RoomQuery {
+    next: Option<usize>,
+    #[serde(default)]
+    spectator: bool,
+}
+
+#[derive(Default)]
+struct ServerState {
+    peers: HashMap<Uuid, UnboundedSender<Message>>,
+    rooms: Rooms,
+}
+
+impl ServerState {
+    fn send(&self, peer: &Uuid, event: &PeerEvent) {
+        let sender = match self.peers.get(peer) {
+            Some(sender) => sender,
+            None => return,
+        };
+
+        match serde_json::to_string(event) {
+            Ok(text) => {
+                let _ = sender.send(Message::Text(text));
+            }
+            Err(e) => error!("Couldn't serialize event : {}", e),
+        }
+    }
+}
+
+type SharedState = Arc<Mutex<ServerState>>;
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt()
+        .compact()
+        .with_target(false)
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    let mut addr = DEFAULT_ADDR.to_string();
+    let mut spectator_slots = DEFAULT_SPECTATOR_SLOTS;
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--spectator-slots" => {
+                spectator_slots = match args.next().map(|slots| slots.parse()) {
+                    Some(Ok(slots)) => slots,
+                    _ => {
+                        error!("--spectator-slots needs a number");
+                        std::process::exit(1);
+                    }
+                }
+            }
+            _ => addr = arg,
+        }
+    }
+
+    let addr: SocketAddr = match addr.parse() {
+        Ok(addr) => addr,
+        Err(e) => {
+            error!("Invalid address {} : {}", addr, e);
+            std::process::exit(1);
+        }
+    };
+
+    let state: SharedState = Arc::new(Mutex::new(ServerState {
+        rooms: Rooms::new(spectator_slots),
+        ..Default::default()
+    }));
+
+    let app = Router::new()
+        .route("/:room", get(ws_handler))
+        .with_state(state);
+
+    info!("Signaling server listening on {}", addr);
+
+    if let Err(e) = axum::Server::bind(&addr)
+        .serve(app.into_make_service())
+        .await
+    {
+        error!("Signaling server stopped : {}", e);
+    }
+}
+
+async fn ws_handler(
+    ws: WebSocketUpgrade,
+    Path(room): Path<String>,
+    Query(query): Query<RoomQuery>,
+    State(state): State<SharedState>,
+) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| handle_peer(socket, room, query, state))
+}
+
+async fn handle_peer(socket: WebSocket, room: String, query: RoomQuery, state: SharedState) {
+    let id = Uuid::new_v4();
+    let key = RoomKey {
+        id: room,
+        next: query.next,
+    };
+    let (mut ws_sender, mut ws_receiver) = socket.split();
+    let (sender, mut receiver) = unbounded_channel::<Message>();
+
+    {
+        let mut state = state.lock().unwrap();
+
+        let others = match query.spectator {
+            true => state.rooms.join_as_spectator(&key, id),
+            false => Some(state.rooms.join_as_player(&key, id)),
+        };
+
+        let others = match others {
+            Some(others) => others,
+            None => {
+                warn!("No spectator slot left in {}", key);
+                return;
+            }
+        };
+
+        state.peers.insert(id, sender);
+        state.send(&id, &PeerEvent::IdAssigned(id));
+
+        //Peers already there start the WebRTC handshake with the newcomer
+        for other in others.iter() {
+            state.send(other, &PeerEvent::NewPeer(id));
+        }
+
+        info!(
+            "{} {} joined {}",
+            match query.spectator {
+                true => "Spectator",
+                false => "Player",
+            },
+            id,
+            key
+        );
+    }
+
+    let forward = tokio::spawn(async move {
+        while let Some(message) = receiver.recv().await {
+            if ws_sender.send(message).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    while let Some(Ok(message)) = ws_receiver.next().await {
+        let text = match message {
+            Message::Text(text) => text,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+
+        match serde_json::from_str::<PeerRequest>(&text) {
+            Ok(PeerRequest::Signal { receiver, data }) => {
+                let state = state.lock().unwrap();
+
+                if state.rooms.same_group(&id, &receiver) {
+                    state.send(&receiver, &PeerEvent::Signal { sender: id, data });
+                } else {
+                    warn!("{} tried to signal {} outside of its room", id, receiver);
+                }
+            }
+            Ok(PeerRequest::KeepAlive) => {}
+            Err(e) => warn!("Invalid request from {} : {}", id, e),
+        }
+    }
+
+    {
+        let mut state = state.lock().unwrap();
+
+        state.peers.remove(&id);
+
+        for other in state.rooms.leave(&id) {
+            state.send(&other, &PeerEvent::PeerLeft(id));
+        }
+
+        info!("{} left {}", id, key);
+    }
+
+    forward.abort();
+}
@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct RoomKey {
+    pub id: String,
+    pub next: Option<usize>,
+}
+
+impl fmt::Display for RoomKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.next {
+            Some(next) => write!(f, "{}?next={}", self.id, next),
+            None => write!(f, "{}", self.id),
+        }
+    }
+}
+
+//With next=N a room is split in groups of N players, peers only ever see their own group
+#[derive(Default)]
+struct Group {
+    players: Vec<Uuid>,
+    spectators: Vec<Uuid>,
+}
+
+impl Group {
+    fn members(&self) -> Vec<Uuid> {
+        self.players
+            .iter()
+            .chain(self.spectators.iter())
+            .copied()
+            .collect()
+    }
+}
+
+#[derive(Default)]
+pub struct Rooms {
+    spectator_slots: usize,
+    groups: HashMap<RoomKey, Vec<Group>>,
+    membership: HashMap<Uuid, (RoomKey, usize)>,
+}
+
+impl Rooms {
+    pub fn new(spectator_slots: usize) -> Self {
+        Self {
+            spectator_slots,
+            ..Default::default()
+        }
+    }
+
+    //Returns the peers already in the group
+    pub fn join_as_player(&mut self, key: &RoomKey, id: Uuid) -> Vec<Uuid> {
+        let groups = self.groups.entry(key.clone()).or_default();
+
+        let is_full = match (groups.last(), key.next) {
+            (Some(group), Some(next)) => group.players.len() >= next,
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+
+        if is_full {
+            groups.push(Group::default());
+        }
+
+        let index = groups.len() - 1;
+        let group = &mut groups[index];
+        let others = group.members();
+
+        group.players.push(id);
+        self.membership.insert(id, (key.clone(), index));
+
+        others
+    }
+
+    //Spectators watch the latest group of the room, None when its slots are taken or nobody is playing
+    pub fn join_as_spectator(&mut self, key: &RoomKey, id: Uuid) -> Option<Vec<Uuid>> {
+        let groups = self.groups.get_mut(key)?;
+        let index = groups.len().checked_sub(1)?;
+        let group = &mut groups[index];
+
+        if group.spectators.len() >= self.spectator_slots {
+            return None;
+        }
+
+        let others = group.members();
+
+        group.spectators.push(id);
+        self.membership.insert(id, (key.clone(), index));
+
+        Some(others)
+    }
+
+    pub fn same_group(&self, first: &Uuid, second: &Uuid) -> bool {
+        match (self.membership.get(first), self.membership.get(second)) {
+            (Some(first), Some(second)) => first == second,
+            _ => false,
+        }
+    }
+
+    //Returns the peers left in the group
+    pub fn leave(&mut self, id: &Uuid) -> Vec<Uuid> {
+        let (key, index) = match self.membership.remove(id) {
+            Some(membership) => membership,
+            None => return vec![],
+        };
+
+        let groups = match self.groups.get_mut(&key) {
+            Some(groups) => groups,
+            None => return vec![],
+        };
+
+        let group = &mut groups[index];
+        group.players.retain(|player| player != id);
+        group.spectators.retain(|spectator| spectator != id);
+
+        let others = group.members();
+
+        //Groups are indexed, only the trailing empty ones can go
+        while groups
+            .last()
+            .is_some_and(|group| group.players.is_empty() && group.spectators.is_empty())
+        {
+            groups.pop();
+        }
+
+        if groups.is_empty() {
+            self.groups.remove(&key);
+        }
+
+        others
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(next: Option<usize>) -> RoomKey {
+        RoomKey {
+            id: "room".to_string(),
+            next,
+        }
+    }
+
+    fn peer(id: u128) -> Uuid {
+        Uuid::from_u128(id)
+    }
+
+    #[test]
+    fn players_are_split_in_groups_of_next() {
+        let mut rooms = Rooms::new(0);
+        let key = key(Some(2));
+
+        assert!(rooms.join_as_player(&key, peer(1)).is_empty());
+        assert_eq!(rooms.join_as_player(&key, peer(2)), vec![peer(1)]);
+        assert!(rooms.join_as_player(&key, peer(3)).is_empty());
+
+        assert!(rooms.same_group(&peer(1), &peer(2)));
+        assert!(!rooms.same_group(&peer(2), &peer(3)));
+    }
+
+    #[test]
+    fn rooms_without_next_have_one_group() {
+        let mut rooms = Rooms::new(0);
+        let key = key(None);
+
+        for id in 1..=5 {
+            rooms.join_as_player(&key, peer(id));
+        }
+
+        assert_eq!(rooms.join_as_player(&key, peer(6)).len(), 5);
+        assert!(rooms.same_group(&peer(1), &peer(6)));
+    }
+
+    #[test]
+    fn spectators_watch_the_latest_group_within_the_slots() {
+        let mut rooms = Rooms::new(1);
+        let key = key(Some(2));
+
+        assert_eq!(rooms.join_as_spectator(&key, peer(10)), None);
+
+        rooms.join_as_player(&key, peer(1));
+        rooms.join_as_player(&key, peer(2));
+        rooms.join_as_player(&key, peer(3));
+
+        assert_eq!(rooms.join_as_spectator(&key, peer(10)), Some(vec![peer(3)]));
+        assert_eq!(rooms.join_as_spectator(&key, peer(11)), None);
+        assert!(rooms.same_group(&peer(3), &peer(10)));
+        assert!(!rooms.same_group(&peer(1), &peer(10)));
+    }
+
+    #[test]
+    fn leaving_drops_trailing_empty_groups() {
+        let mut rooms = Rooms::new(0);
+        let key = key(Some(2));
+
+        rooms.join_as_player(&key, peer(1));
+        rooms.join_as_player(&key, peer(2));
+        rooms.join_as_player(&key, peer(3));
+
+        assert!(rooms.leave(&peer(3)).is_empty());
+        assert_eq!(rooms.leave(&peer(1)), vec![peer(2)]);
+
+        //The second group is gone, so the first one takes the next player
+        assert_eq!(rooms.join_as_player(&key, peer(4)), vec![peer(2)]);
+        assert!(rooms.same_group(&peer(2), &peer(4)));
+
+        rooms.leave(&peer(2));
+        rooms.leave(&peer(4));
+
+        assert!(rooms.groups.is_empty());
+        assert!(!rooms.same_group(&peer(2), &peer(4)));
+    }
+}